@@ -0,0 +1,241 @@
+//! Runtime-agnostic asynchronous waiting using `async-io`.
+
+#[cfg(unix)]
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use async_io::Timer;
+#[cfg(unix)]
+use async_io::Async;
+#[cfg(unix)]
+use crate::{CreationKind, EventBuffer, Notification, Readiness, WaitError, backend, scan_changes};
+#[cfg(windows)]
+use crate::CancellationToken;
+use crate::{Options, WaitAction, WaitOutcome, WaitState, file_state, inotify_disabled, open_result, take_error, take_opened, timed_out};
+
+impl Options {
+    /// Opens the file once it's available without blocking the thread and without depending on
+    /// tokio.
+    ///
+    /// This is the equivalent of `open_when_created_async` built on `async-io` so it works with
+    /// `smol`, `async-std` and any other executor. It's named differently so that both features
+    /// can be enabled at the same time. It honors the same settings as the blocking version with
+    /// the same exceptions as `open_when_created_async`, the timer of `async-io` is used instead
+    /// of the tokio one.
+    ///
+    /// Waiting can be cancelled by dropping the returned future.
+    ///
+    /// On Windows the events can not be registered with the reactor so waiting happens in a thread
+    /// of the `blocking` pool which is stopped when the future is dropped.
+    #[inline]
+    pub async fn open_when_created_async_io<P: AsRef<Path>>(&self, path: P) -> io::Result<File> {
+        // Monomorphise the method to avoid machine code blowup
+        self.internal_open_when_created_async_io(path.as_ref()).await
+    }
+
+    async fn internal_open_when_created_async_io(&self, path: &Path) -> io::Result<File> {
+        match self.time_limit() {
+            Some(timeout) => {
+                let timer = async {
+                    Timer::after(timeout).await;
+                    Err(timed_out())
+                };
+                futures_lite::future::or(self.wait_async_io(path), timer).await
+            },
+            None => self.wait_async_io(path).await,
+        }
+    }
+
+    #[cfg(unix)]
+    async fn wait_async_io(&self, path: &Path) -> io::Result<File> {
+        if self.polling_only {
+            return self.try_fallback_open_async_io(path, inotify_disabled()).await;
+        }
+
+        // Fast path avoiding inotify syscalls if the file is already there.
+        match self.attempt_open(path, CreationKind::NoEvent) {
+            Ok(Some(file)) => {
+                self.wait_unchanged_async_io(path).await?;
+                return Ok(file);
+            },
+            Ok(None) => (),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => (),
+            Err(error) => return Err(error),
+        }
+
+        let (dir, file_name) = match self.watch_target(path) {
+            Err(error) => return Err(WaitError::AddWatch(error).into()),
+            Ok(Some(split)) => split,
+            Ok(None) => return self.try_fallback_open_async_io(path, io::Error::new(io::ErrorKind::InvalidInput, "the path doesn't contain a file name")).await,
+        };
+
+        let mut watcher = match backend::DirWatcher::new(self) {
+            Ok(watcher) => watcher,
+            Err(error) => return self.try_fallback_open_async_io(path, WaitError::inotify_init(error).into()).await,
+        };
+
+        if let Err(error) = watcher.watch(&dir) {
+            return self.try_fallback_open_async_io(path, WaitError::add_watch(error).into()).await;
+        }
+
+        match Async::new(watcher) {
+            Ok(watcher) => self.wait_for_file_async_io(watcher, path, file_name).await,
+            Err(error) => self.try_fallback_open_async_io(path, error).await,
+        }
+    }
+
+    #[cfg(windows)]
+    async fn wait_async_io(&self, path: &Path) -> io::Result<File> {
+        if self.polling_only {
+            return self.try_fallback_open_async_io(path, inotify_disabled()).await;
+        }
+
+        let token = CancellationToken::new()?;
+        let _guard = CancelOnDrop(token.clone());
+        let options = self.clone();
+        let path = path.to_owned();
+        blocking::unblock(move || options.open_when_created_cancellable(path, &token)).await
+    }
+
+    async fn try_fallback_open_async_io(&self, path: &Path, inotify_error: io::Error) -> io::Result<File> {
+        if self.only_accept_rename {
+            return Err(inotify_error);
+        }
+        let mut state = WaitState::polling(self.wait_config());
+        let mut last = None;
+        let mut action = state.start();
+        loop {
+            action = match action {
+                WaitAction::Open(kind) => state.open_result(open_result(self.attempt_open(path, kind), &mut last)),
+                WaitAction::Sleep(delay) => {
+                    Timer::after(delay).await;
+                    state.slept()
+                },
+                WaitAction::Wait => unreachable!("polling never waits for events"),
+                WaitAction::Return(WaitOutcome::Opened(_)) => {
+                    let file = take_opened(&mut last);
+                    self.wait_unchanged_async_io(path).await?;
+                    return Ok(file);
+                },
+                WaitAction::Return(WaitOutcome::Unavailable) => return Err(inotify_error),
+                WaitAction::Return(outcome) => return Err(take_error(&mut last, outcome).into()),
+            }
+        }
+    }
+
+    /// Asynchronous version of `wait_unchanged`.
+    async fn wait_unchanged_async_io(&self, path: &Path) -> io::Result<()> {
+        let quiet = match self.quiescence {
+            Some(quiet) => quiet,
+            None => return Ok(()),
+        };
+
+        let mut last = file_state(path)?;
+        loop {
+            Timer::after(quiet).await;
+            let current = file_state(path)?;
+            if current == last {
+                return Ok(());
+            }
+            last = current;
+        }
+    }
+
+    #[cfg(unix)]
+    async fn wait_for_file_async_io(&self, mut watcher: Async<backend::DirWatcher>, path: &Path, file_name: &OsStr) -> io::Result<File> {
+        let mut buffer = EventBuffer::new(self);
+        let mut state = WaitState::watching(self.wait_config());
+        let mut last = None;
+        let mut action = state.start();
+
+        loop {
+            action = match action {
+                WaitAction::Open(kind) => state.open_result(open_result(self.attempt_open(path, kind), &mut last)),
+                WaitAction::Wait => {
+                    let readiness = loop {
+                        let readable = match self.recheck_interval {
+                            Some(interval) => {
+                                // Time to re-check in case events were lost
+                                let timer = async {
+                                    Timer::after(interval).await;
+                                    None
+                                };
+                                match futures_lite::future::or(async { Some(watcher.readable().await) }, timer).await {
+                                    Some(readable) => readable,
+                                    None => break None,
+                                }
+                            },
+                            None => watcher.readable().await,
+                        };
+                        if let Err(error) = readable {
+                            return self.try_fallback_open_async_io(path, error).await;
+                        }
+
+                        // SAFETY: reading the changes doesn't replace or close the descriptor
+                        match scan_changes(unsafe { watcher.get_mut() }, &mut buffer, file_name) {
+                            Ok(Some(readiness)) => break Some(readiness),
+                            Ok(None) => (),
+                            Err(error) if error.kind() == io::ErrorKind::WouldBlock => (),
+                            Err(error) => return self.try_fallback_open_async_io(path, error).await,
+                        }
+                    };
+
+                    state.notify(match readiness {
+                        Some(Readiness::Available(kind)) => Notification::Available(kind),
+                        Some(Readiness::Maybe) => Notification::Maybe,
+                        None => Notification::Recheck,
+                    })
+                },
+                WaitAction::Sleep(cooldown) => {
+                    Timer::after(cooldown).await;
+                    state.slept()
+                },
+                WaitAction::Return(WaitOutcome::Opened(_)) => {
+                    let file = take_opened(&mut last);
+                    self.wait_unchanged_async_io(path).await?;
+                    return Ok(file);
+                },
+                WaitAction::Return(outcome) => return Err(take_error(&mut last, outcome).into()),
+            }
+        }
+    }
+}
+
+/// Stops the blocking wait if the future waiting for it is dropped.
+#[cfg(windows)]
+struct CancelOnDrop(CancellationToken);
+
+#[cfg(windows)]
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn wait_async_io() {
+        use std::io::Read;
+
+        let test_string = "satoshi nakamoto";
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(&file_path_thread, test_string).unwrap();
+        });
+        let options = crate::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone());
+        let mut file = async_io::block_on(options.open_when_created_async_io(&file_path)).unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, test_string);
+        thread.join().unwrap();
+
+        let missing = temp_dir.join("missing");
+        let error = async_io::block_on(options.timeout(std::time::Duration::from_millis(100)).open_when_created_async_io(&missing)).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+    }
+}
@@ -0,0 +1,80 @@
+//! Abstraction of time used for timeouts and polling.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Source of time used when waiting.
+///
+/// All deadlines and sleeps between polling attempts go through the clock set by
+/// `Options::with_clock` which allows tests to control time without actually sleeping. Note
+/// that blocking on inotify events still waits for real time, the clock only decides how long.
+/// The same applies to sleeping in waits which can be cancelled.
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Returns the current time.
+    fn now(&self) -> Instant;
+
+    /// Blocks the current thread for `duration`.
+    fn sleep(&self, duration: Duration);
+}
+
+/// The real clock backed by `Instant::now` and `std::thread::sleep`.
+///
+/// This is used unless a different clock is set.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[inline]
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    #[inline]
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    /// Clock advancing only when sleeping.
+    #[derive(Debug)]
+    struct FakeClock {
+        now: Mutex<Instant>,
+        slept: Mutex<Duration>,
+    }
+
+    impl super::Clock for FakeClock {
+        fn now(&self) -> Instant {
+            *self.now.lock().unwrap()
+        }
+
+        fn sleep(&self, duration: Duration) {
+            *self.now.lock().unwrap() += duration;
+            *self.slept.lock().unwrap() += duration;
+        }
+    }
+
+    #[test]
+    fn fake_clock_timeout() {
+        let clock = std::sync::Arc::new(FakeClock {
+            now: Mutex::new(Instant::now()),
+            slept: Mutex::new(Duration::from_secs(0)),
+        });
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let start = Instant::now();
+        let error = crate::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .polling_only(Duration::from_secs(60))
+            .timeout(Duration::from_secs(3600))
+            .with_clock(clock.clone())
+            .open_when_created(&file_path)
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+        assert_eq!(*clock.slept.lock().unwrap(), Duration::from_secs(3600));
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,104 @@
+//! Declarative configuration loadable from configuration files.
+
+use std::fs::OpenOptions;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::Options;
+
+/// Serializable subset of `Options`.
+///
+/// `Options` can't be deserialized directly since `OpenOptions` can't be so this describes the
+/// access to the file using plain flags instead. Missing fields have the same defaults as
+/// `Options::with_open_options` except that the file is opened for reading by default.
+///
+/// Intervals are in milliseconds to make them easy to write in configuration files.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct OptionsConfig {
+    /// Open the file for reading.
+    pub read: bool,
+    /// Open the file for writing.
+    pub write: bool,
+    /// Open the file for appending.
+    pub append: bool,
+    /// See `Options::retry_on_fluke`.
+    pub retry_on_fluke: bool,
+    /// See `Options::assume_create_is_atomic`.
+    pub assume_create_is_atomic: bool,
+    /// See `Options::wait_for_nonempty`.
+    pub wait_for_nonempty: bool,
+    /// See `Options::allow_empty_file`.
+    pub allow_empty_file: bool,
+    /// See `Options::polling_fallback_interval`.
+    pub polling_interval_ms: Option<u64>,
+    /// See `Options::timeout`.
+    pub timeout_ms: Option<u64>,
+}
+
+impl OptionsConfig {
+    /// Converts the configuration to `Options`.
+    pub fn into_options(self) -> Options {
+        let mut open_options = OpenOptions::new();
+        open_options.read(self.read).write(self.write).append(self.append);
+
+        let mut options = Options::with_open_options(open_options)
+            .retry_on_fluke(self.retry_on_fluke)
+            .assume_create_is_atomic(self.assume_create_is_atomic)
+            .wait_for_nonempty(self.wait_for_nonempty)
+            .allow_empty_file(self.allow_empty_file);
+        if let Some(interval) = self.polling_interval_ms {
+            options = options.polling_fallback_interval(Duration::from_millis(interval));
+        }
+        if let Some(timeout) = self.timeout_ms {
+            options = options.timeout(Duration::from_millis(timeout));
+        }
+        options
+    }
+}
+
+impl Default for OptionsConfig {
+    fn default() -> Self {
+        OptionsConfig {
+            read: true,
+            write: false,
+            append: false,
+            retry_on_fluke: false,
+            assume_create_is_atomic: false,
+            wait_for_nonempty: false,
+            allow_empty_file: false,
+            polling_interval_ms: None,
+            timeout_ms: None,
+        }
+    }
+}
+
+impl From<OptionsConfig> for Options {
+    fn from(config: OptionsConfig) -> Self {
+        config.into_options()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn deserialize() {
+        use serde::Deserialize;
+
+        let fields = vec![("write", true), ("retry_on_fluke", true)];
+        let deserializer = serde::de::value::MapDeserializer::<_, serde::de::value::Error>::new(fields.into_iter());
+        let config = super::OptionsConfig::deserialize(deserializer).unwrap();
+        let expected = super::OptionsConfig {
+            write: true,
+            retry_on_fluke: true,
+            ..Default::default()
+        };
+        assert_eq!(config, expected);
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        std::fs::write(&file_path, "satoshi nakamoto").unwrap();
+        let file = config.into_options().open_when_created(&file_path).unwrap();
+        assert!(file.metadata().unwrap().is_file());
+    }
+}
@@ -0,0 +1,147 @@
+//! Waiting for files to be deleted.
+
+use std::io;
+use std::path::Path;
+use crate::{ChangeSource, EventBuffer, OpenResult, Options, WaitContext, WaitError, WaitState, Wakeup, backend, inotify_disabled, next_poll, split_path};
+
+impl Options {
+    /// Waits until there's no file at `path`.
+    ///
+    /// This is the inverse of `wait_only` useful e.g. for waiting until a lock file is removed.
+    /// The file is considered deleted when it's unlinked or moved away. If its parent directory
+    /// doesn't exist the file doesn't exist either so this returns immediately.
+    ///
+    /// If `follow_symlinks` is `false` a dangling symlink still counts as an existing file.
+    /// The open options and the options concerning creation of the file are not used.
+    ///
+    /// Note that the file may be created again right after this function returns.
+    pub fn wait_until_deleted<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let context = self.wait_context(None);
+        self.wait_deleted(path.as_ref(), &context).map_err(Into::into)
+    }
+
+    fn wait_deleted(&self, path: &Path, context: &WaitContext<'_>) -> Result<(), WaitError> {
+        if self.polling_only {
+            return self.poll_deleted(path, WaitError::InotifyInit(inotify_disabled()), context);
+        }
+
+        let dir = match split_path(path) {
+            Some((dir, _)) => dir,
+            None => return self.poll_deleted(path, WaitError::AddWatch(io::Error::new(io::ErrorKind::InvalidInput, "the path doesn't contain a file name")), context),
+        };
+
+        let mut options = self.clone();
+        options.watch_deletions = true;
+        let mut watcher = match backend::DirWatcher::new(&options) {
+            Ok(watcher) => watcher,
+            Err(error) => return self.poll_deleted(path, WaitError::inotify_init(error), context),
+        };
+        match watcher.watch(dir) {
+            Ok(_) => (),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(error) => return self.poll_deleted(path, WaitError::add_watch(error), context),
+        }
+
+        let mut buffer = EventBuffer::new(self);
+        // Any change in the directory leads to a check since it's cheap
+        while self.file_exists(path)? {
+            loop {
+                match context.limited(self.recheck_interval).wait_readable(&watcher) {
+                    Ok(Wakeup::Readable) => (),
+                    Ok(Wakeup::TimedOut) if !context.is_expired() => break,
+                    Ok(Wakeup::TimedOut) => return Err(context.timed_out()),
+                    Ok(Wakeup::Cancelled) => return Err(WaitError::Cancelled),
+                    Err(error) => return self.poll_deleted(path, WaitError::ReadEvents(error), context),
+                }
+
+                match watcher.read_changes(&mut buffer, |_, _| ()) {
+                    Ok(()) => break,
+                    Err(error) if error.kind() == io::ErrorKind::WouldBlock => (),
+                    Err(error) => return self.poll_deleted(path, WaitError::ReadEvents(error), context),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn poll_deleted(&self, path: &Path, inotify_error: WaitError, context: &WaitContext<'_>) -> Result<(), WaitError> {
+        let mut state = WaitState::polling(self.wait_config());
+        loop {
+            context.check_cancelled()?;
+
+            if !self.file_exists(path)? {
+                return Ok(());
+            }
+
+            // The file still existing is what not finding it is when waiting for creation
+            match next_poll(&mut state, OpenResult::NotFound, context) {
+                Ok(true) => (),
+                Ok(false) => return Err(inotify_error),
+                Err(WaitError::TimedOut { .. }) => return Err(WaitError::PollingTimedOut { watch: Box::new(inotify_error), last_open: None }),
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    fn file_exists(&self, path: &Path) -> Result<bool, WaitError> {
+        let result = if self.follow_symlinks {
+            std::fs::metadata(path)
+        } else {
+            std::fs::symlink_metadata(path)
+        };
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(error) => Err(WaitError::Open(error)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn wait_until_deleted() {
+        let options = crate::Options::with_open_options(std::fs::OpenOptions::new());
+        let polling = options.clone().polling_only(std::time::Duration::from_millis(10));
+
+        for options in [options, polling] {
+            let temp_dir = mktemp::Temp::new_dir().unwrap();
+            let file_path = temp_dir.join("test");
+            options.wait_until_deleted(&file_path).unwrap();
+            options.wait_until_deleted(temp_dir.join("missing").join("test")).unwrap();
+
+            std::fs::write(&file_path, "satoshi nakamoto").unwrap();
+            let file_path_thread = file_path.clone();
+            let thread = std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                std::fs::rename(&file_path_thread, file_path_thread.with_extension("old")).unwrap();
+            });
+            options.wait_until_deleted(&file_path).unwrap();
+            assert!(!file_path.exists());
+            thread.join().unwrap();
+
+            std::fs::write(&file_path, "satoshi nakamoto").unwrap();
+            let file_path_thread = file_path.clone();
+            let thread = std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                std::fs::remove_file(&file_path_thread).unwrap();
+            });
+            options.wait_until_deleted(&file_path).unwrap();
+            assert!(!file_path.exists());
+            thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn polling_timed_out() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        std::fs::write(&file_path, "satoshi nakamoto").unwrap();
+        let options = crate::Options::with_open_options(std::fs::OpenOptions::new())
+            .polling_only(std::time::Duration::from_millis(10))
+            .timeout(std::time::Duration::from_millis(50));
+        let error = options.wait_deleted(&file_path, &options.wait_context(None)).unwrap_err();
+        assert!(matches!(error, crate::WaitError::PollingTimedOut { last_open: None, .. }));
+    }
+}
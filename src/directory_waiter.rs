@@ -0,0 +1,165 @@
+//! Waiting for a sequence of files in the same directory.
+
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use crate::{EventBuffer, Options, WaitError, backend, inotify_disabled};
+
+/// Waits for files in a single directory reusing the same watch.
+///
+/// `Options::open_when_created` sets up a new watch on each call which is wasteful when
+/// processing many files in the same directory, e.g. in a spool. This keeps one watch alive for
+/// the whole lifetime of the waiter. The buffer for the events is reused as well so setting a
+/// large `event_buffer_size` doesn't cause an allocation for each file.
+///
+/// If the watch fails, e.g. because the directory was removed, it's set up again on the next call
+/// falling back to polling if that fails and polling is enabled.
+pub struct DirectoryWaiter {
+    options: Options,
+    dir: PathBuf,
+    watcher: Option<backend::DirWatcher>,
+    buffer: EventBuffer,
+}
+
+impl DirectoryWaiter {
+    /// Starts watching `dir`.
+    ///
+    /// Returns an error if the watch couldn't be set up and polling fallback is disabled.
+    pub fn new<P: Into<PathBuf>>(dir: P, options: Options) -> io::Result<Self> {
+        let mut waiter = DirectoryWaiter {
+            buffer: EventBuffer::new(&options),
+            options,
+            dir: dir.into(),
+            watcher: None,
+        };
+
+        match waiter.watch() {
+            Ok(watcher) => waiter.watcher = Some(watcher),
+            Err(error) if waiter.options.polling_fallback.is_none() => return Err(error.into()),
+            Err(_) => (),
+        }
+        Ok(waiter)
+    }
+
+    /// Opens the file `name` in the directory once it's available.
+    ///
+    /// This behaves the same as `Options::open_when_created` with the path of the file in the
+    /// directory. `name` must be a single path component, otherwise an error with
+    /// `ErrorKind::InvalidInput` is returned.
+    pub fn next_file<N: AsRef<OsStr>>(&mut self, name: N) -> io::Result<File> {
+        let name = name.as_ref();
+        let mut components = Path::new(name).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(component)), None) if component == name => (),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "the name must be a single path component")),
+        }
+
+        let path = self.dir.join(name);
+        let context = self.options.wait_context(None);
+        let options = &self.options;
+        let attempt = |path: &Path, kind| options.attempt_open(path, kind);
+
+        if self.watcher.is_none() {
+            match self.watch() {
+                Ok(watcher) => self.watcher = Some(watcher),
+                Err(error) => return options.try_fallback_open(&path, error, &context, &attempt).map(|(file, _)| file).map_err(Into::into),
+            }
+        }
+
+        let watcher = self.watcher.as_mut().expect("the watcher was just set");
+        let result = options.wait_for_file(watcher, &mut self.buffer, &path, name, &context, &attempt);
+        // The watch is broken, e.g. the directory was removed, so it's set up again next time
+        if context.progress.get().watch_lost {
+            self.watcher = None;
+        }
+        result.map(|(file, _)| file).map_err(Into::into)
+    }
+
+    fn watch(&self) -> Result<backend::DirWatcher, WaitError> {
+        if self.options.polling_only {
+            return Err(WaitError::InotifyInit(inotify_disabled()));
+        }
+
+        let mut watcher = backend::DirWatcher::new(&self.options).map_err(WaitError::inotify_init)?;
+        watcher.watch(&self.dir).map_err(WaitError::add_watch)?;
+        Ok(watcher)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn next_file() {
+        use std::io::Read;
+
+        let options = crate::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone());
+        let polling = options.clone().polling_only(std::time::Duration::from_millis(10));
+
+        for options in [options, polling] {
+            let temp_dir = mktemp::Temp::new_dir().unwrap();
+            let dir_thread = temp_dir.to_path_buf();
+            let thread = std::thread::spawn(move || {
+                for i in 0..3 {
+                    std::thread::sleep(std::time::Duration::from_millis(50));
+                    std::fs::write(dir_thread.join("job.tmp"), i.to_string()).unwrap();
+                    std::fs::rename(dir_thread.join("job.tmp"), dir_thread.join(format!("{}.job", i))).unwrap();
+                }
+            });
+            let mut waiter = super::DirectoryWaiter::new(&*temp_dir, options).unwrap();
+            for i in 0..3 {
+                let mut file = waiter.next_file(format!("{}.job", i)).unwrap();
+                let mut contents = String::new();
+                file.read_to_string(&mut contents).unwrap();
+                assert_eq!(contents, i.to_string());
+            }
+            thread.join().unwrap();
+            assert_eq!(waiter.next_file("a/b").unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        }
+    }
+    #[test]
+    fn directory_recreated() {
+        use crate::ParentRemovedPolicy;
+        use std::time::Duration;
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let dir = temp_dir.join("dir");
+        let options = crate::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .timeout(Duration::from_secs(5));
+
+        std::fs::create_dir(&dir).unwrap();
+        let mut waiter = super::DirectoryWaiter::new(&dir, options.clone().on_parent_removed(ParentRemovedPolicy::Error)).unwrap();
+        let dir_thread = dir.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            std::fs::remove_dir(&dir_thread).unwrap();
+        });
+        waiter.next_file("1").unwrap_err();
+        thread.join().unwrap();
+        std::fs::create_dir(&dir).unwrap();
+        let dir_thread = dir.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            std::fs::write(dir_thread.join("2"), "2").unwrap();
+        });
+        // Waiting on the dead watch would time out
+        waiter.next_file("2").unwrap();
+        thread.join().unwrap();
+
+        let mut waiter = super::DirectoryWaiter::new(&dir, options.on_parent_removed(ParentRemovedPolicy::RecreateWatch)).unwrap();
+        let dir_thread = dir.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            std::fs::remove_dir_all(&dir_thread).unwrap();
+            std::thread::sleep(Duration::from_millis(100));
+            std::fs::create_dir(&dir_thread).unwrap();
+            std::thread::sleep(Duration::from_millis(100));
+            std::fs::write(dir_thread.join("3"), "3").unwrap();
+            std::thread::sleep(Duration::from_millis(100));
+            std::fs::write(dir_thread.join("4"), "4").unwrap();
+        });
+        waiter.next_file("3").unwrap();
+        waiter.next_file("4").unwrap();
+        thread.join().unwrap();
+    }
+}
@@ -0,0 +1,164 @@
+//! Error type describing what went wrong while waiting.
+
+use std::fmt;
+use std::io;
+
+/// Error returned when waiting for the file fails.
+///
+/// The variants describe at which stage the failure happened. Errors of the underlying operations
+/// are preserved and available as the source of this error.
+///
+/// On platforms not using inotify `InotifyInit` and `AddWatch` refer to the analogous operations
+/// of the platform API.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum WaitError {
+    /// Initialization of inotify failed.
+    InotifyInit(io::Error),
+    /// Adding the watch for the parent directory failed.
+    AddWatch(io::Error),
+    /// The system limit of watches or inotify instances was reached.
+    ///
+    /// On Linux the limits can be raised using `fs.inotify.max_user_watches` and
+    /// `fs.inotify.max_user_instances` sysctls. This is only returned if the polling fallback is
+    /// not enabled.
+    WatchLimit(io::Error),
+    /// Waiting for or reading the events failed or the watch was removed.
+    ReadEvents(io::Error),
+    /// Opening the file failed.
+    Open(io::Error),
+    /// The file kept disappearing before it could be opened more times than allowed.
+    ///
+    /// See `Options::max_fluke_retries`.
+    FlukeRetriesExhausted(io::Error),
+    /// The parent directory was removed while waiting.
+    ///
+    /// See `Options::on_parent_removed`.
+    ParentRemoved,
+    /// The file wasn't opened before the timeout elapsed.
+    ///
+    /// The fields tell how far the producer got which helps distinguishing a slow producer from
+    /// one that never started.
+    TimedOut {
+        /// The file was observed to exist at some point, e.g. it existed but wasn't ready yet.
+        saw_create: bool,
+        /// The producer was reported to close the file after writing it.
+        ///
+        /// This is never `true` on platforms not reporting closing of files.
+        saw_close_write: bool,
+    },
+    /// The timeout elapsed while polling because the watch failed.
+    ///
+    /// The source of this error is the error of the last attempt to open the file if there was
+    /// one, otherwise the error of the watch.
+    PollingTimedOut {
+        /// The error which caused falling back to polling.
+        watch: Box<WaitError>,
+        /// The last error which was retried, e.g. `ErrorKind::PermissionDenied`, otherwise the
+        /// error of the last attempt to open the file, usually `ErrorKind::NotFound`.
+        ///
+        /// `None` if the file existed but it wasn't ready yet, e.g. it was too small, and when
+        /// waiting for the file to be deleted.
+        last_open: Option<io::Error>,
+    },
+    /// Waiting was cancelled using `CancellationToken`.
+    Cancelled,
+}
+
+impl WaitError {
+    /// Classifies the error of initializing the watcher.
+    pub(crate) fn inotify_init(error: io::Error) -> Self {
+        if is_limit(&error) {
+            WaitError::WatchLimit(error)
+        } else {
+            WaitError::InotifyInit(error)
+        }
+    }
+
+    /// Classifies the error of adding the watch.
+    pub(crate) fn add_watch(error: io::Error) -> Self {
+        if is_limit(&error) {
+            WaitError::WatchLimit(error)
+        } else {
+            WaitError::AddWatch(error)
+        }
+    }
+
+    /// Returns the kind of the corresponding `io::Error`.
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            WaitError::InotifyInit(error) | WaitError::AddWatch(error) | WaitError::WatchLimit(error) | WaitError::ReadEvents(error) | WaitError::Open(error) | WaitError::FlukeRetriesExhausted(error) => error.kind(),
+            WaitError::ParentRemoved => io::ErrorKind::NotFound,
+            WaitError::TimedOut { .. } | WaitError::PollingTimedOut { .. } => io::ErrorKind::TimedOut,
+            WaitError::Cancelled => io::ErrorKind::Interrupted,
+        }
+    }
+}
+
+impl fmt::Display for WaitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WaitError::InotifyInit(_) => f.write_str("failed to initialize inotify"),
+            WaitError::AddWatch(_) => f.write_str("failed to watch the parent directory"),
+            WaitError::WatchLimit(_) => f.write_str("the limit of watches or inotify instances was reached"),
+            WaitError::ReadEvents(_) => f.write_str("failed to wait for events"),
+            WaitError::Open(_) => f.write_str("failed to open the file"),
+            WaitError::FlukeRetriesExhausted(_) => f.write_str("the file was deleted before it could be opened too many times, the retry budget was exhausted"),
+            WaitError::ParentRemoved => f.write_str("the parent directory was removed while waiting for the file"),
+            WaitError::TimedOut { saw_create: false, .. } => f.write_str("timed out while waiting for the file to be created"),
+            WaitError::TimedOut { saw_create: true, .. } => f.write_str("timed out while waiting for the file to be ready"),
+            WaitError::PollingTimedOut { .. } => f.write_str("timed out while polling for the file"),
+            WaitError::Cancelled => f.write_str("waiting for the file to be created was cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for WaitError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WaitError::InotifyInit(error) | WaitError::AddWatch(error) | WaitError::WatchLimit(error) | WaitError::ReadEvents(error) | WaitError::Open(error) | WaitError::FlukeRetriesExhausted(error) => Some(error),
+            WaitError::PollingTimedOut { last_open: Some(error), .. } => Some(error),
+            WaitError::PollingTimedOut { watch, last_open: None } => Some(&**watch),
+            WaitError::ParentRemoved | WaitError::TimedOut { .. } | WaitError::Cancelled => None,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_limit(error: &io::Error) -> bool {
+    matches!(error.raw_os_error(), Some(libc::ENOSPC) | Some(libc::EMFILE))
+}
+
+#[cfg(windows)]
+fn is_limit(error: &io::Error) -> bool {
+    matches!(error.raw_os_error(), Some(crate::backend::ERROR_TOO_MANY_OPEN_FILES) | Some(crate::backend::ERROR_NOT_ENOUGH_QUOTA))
+}
+
+/// Converts the error preserving its kind.
+///
+/// The error of the final open attempt is returned unchanged to stay compatible with the methods
+/// returning `io::Error`.
+impl From<WaitError> for io::Error {
+    fn from(error: WaitError) -> Self {
+        match error {
+            WaitError::Open(error) => error,
+            error => io::Error::new(error.kind(), error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    #[cfg(unix)]
+    fn watch_limit() {
+        use super::WaitError;
+
+        let error = WaitError::add_watch(std::io::Error::from_raw_os_error(libc::ENOSPC));
+        assert!(matches!(error, WaitError::WatchLimit(_)));
+        let error = WaitError::inotify_init(std::io::Error::from_raw_os_error(libc::EMFILE));
+        assert!(matches!(error, WaitError::WatchLimit(_)));
+        let error = WaitError::add_watch(std::io::Error::from_raw_os_error(libc::ENOENT));
+        assert!(matches!(error, WaitError::AddWatch(_)));
+    }
+}
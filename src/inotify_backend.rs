@@ -0,0 +1,213 @@
+//! Watching directories using inotify.
+
+use std::borrow::BorrowMut;
+use std::ffi::OsStr;
+use std::fmt;
+use std::io;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, RawFd};
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use inotify::{Event, EventMask, Inotify, WatchMask};
+use crate::{Change, ChangeSource, CreationKind, Options};
+
+pub(crate) type WatchId = inotify::WatchDescriptor;
+
+/// inotify reports names of changed entries.
+pub(crate) const REPORTS_NAMES: bool = true;
+
+/// inotify reports closing of files opened for writing.
+pub(crate) const REPORTS_CLOSE_WRITE: bool = true;
+
+type EventCallback = dyn FnMut(&Event<&OsStr>) + Send;
+
+/// Observer of raw inotify events shared between clones of `Options`.
+#[derive(Clone)]
+pub(crate) struct EventHook(Arc<Mutex<EventCallback>>);
+
+impl EventHook {
+    pub(crate) fn new<F: FnMut(&Event<&OsStr>) + Send + 'static>(callback: F) -> Self {
+        EventHook(Arc::new(Mutex::new(callback)))
+    }
+
+    fn call(&self, event: &Event<&OsStr>) {
+        // The callback can not break our invariants so a panic in previous call is irrelevant
+        let mut callback = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        callback(event)
+    }
+}
+
+impl fmt::Debug for EventHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EventHook")
+    }
+}
+
+/// Watches directories using either owned or borrowed inotify instance.
+pub(crate) struct DirWatcher<I: BorrowMut<Inotify> = Inotify> {
+    inotify: I,
+    mask: WatchMask,
+    on_event: Option<EventHook>,
+}
+
+impl DirWatcher {
+    pub(crate) fn new(options: &Options) -> io::Result<Self> {
+        Ok(DirWatcher::with_inotify(Inotify::init()?, options))
+    }
+}
+
+impl<I: BorrowMut<Inotify>> DirWatcher<I> {
+    pub(crate) fn with_inotify(inotify: I, options: &Options) -> Self {
+        let mut mask = WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::DELETE_SELF | WatchMask::ONLYDIR;
+        // Creating directories is atomic
+        if options.create_is_atomic || options.expect_directory || options.watch_creations {
+            mask |= WatchMask::CREATE;
+        }
+        if options.watches_modifications() {
+            mask |= WatchMask::MODIFY;
+        }
+        if options.retry_permission_denied {
+            mask |= WatchMask::ATTRIB;
+        }
+        if options.watch_deletions {
+            mask |= WatchMask::DELETE | WatchMask::MOVED_FROM;
+        }
+        mask |= options.extra_watch_mask;
+
+        DirWatcher {
+            inotify,
+            mask,
+            on_event: options.on_event.clone(),
+        }
+    }
+
+    pub(crate) fn watch(&mut self, dir: &Path) -> io::Result<WatchId> {
+        self.inotify.borrow_mut().add_watch(dir, self.mask)
+    }
+
+    pub(crate) fn unwatch(&mut self, watch: WatchId) -> io::Result<()> {
+        self.inotify.borrow_mut().rm_watch(watch)
+    }
+}
+
+impl<I: BorrowMut<Inotify>> ChangeSource for DirWatcher<I> {
+    fn read_changes<F: FnMut(&WatchId, Change<'_>)>(&mut self, buffer: &mut [u8], mut f: F) -> io::Result<()> {
+        let mut received = false;
+        for event in self.inotify.borrow_mut().read_events(buffer)? {
+            received = true;
+            if let Some(on_event) = &self.on_event {
+                on_event.call(&event);
+            }
+
+            if event.mask.contains(EventMask::Q_OVERFLOW) {
+                f(&event.wd, Change::Overflow);
+            } else if event.mask.contains(EventMask::IGNORED) {
+                f(&event.wd, Change::Removed);
+            } else if let (Some(name), Some(kind)) = (event.name, creation_kind(event.mask)) {
+                f(&event.wd, Change::Entry(name, kind));
+            }
+        }
+
+        if received {
+            Ok(())
+        } else {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+    }
+}
+
+impl<I: BorrowMut<Inotify>> AsRawFd for DirWatcher<I> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inotify.borrow().as_raw_fd()
+    }
+}
+
+impl<I: BorrowMut<Inotify>> AsFd for DirWatcher<I> {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        // SAFETY: the descriptor is owned by the inotify instance borrowed for the lifetime
+        unsafe { BorrowedFd::borrow_raw(self.as_raw_fd()) }
+    }
+}
+
+/// Source of changes reporting only those of a single watch.
+///
+/// Used when waiting using a watch owned by the caller which may watch other directories too.
+pub(crate) struct SingleWatch<'a, S> {
+    source: S,
+    watch: &'a WatchId,
+}
+
+impl<'a, S: ChangeSource> SingleWatch<'a, S> {
+    pub(crate) fn new(source: S, watch: &'a WatchId) -> Self {
+        SingleWatch {
+            source,
+            watch,
+        }
+    }
+}
+
+impl<S: ChangeSource> ChangeSource for SingleWatch<'_, S> {
+    fn read_changes<F: FnMut(&WatchId, Change<'_>)>(&mut self, buffer: &mut [u8], mut f: F) -> io::Result<()> {
+        let watch = self.watch;
+        self.source.read_changes(buffer, |id, change| match change {
+            // Overflow is reported without a watch
+            Change::Overflow => f(id, change),
+            _ if id == watch => f(id, change),
+            _ => (),
+        })
+    }
+}
+
+impl<S: ChangeSource> AsRawFd for SingleWatch<'_, S> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.source.as_raw_fd()
+    }
+}
+
+fn creation_kind(mask: EventMask) -> Option<CreationKind> {
+    if mask.contains(EventMask::MOVED_TO) {
+        Some(CreationKind::MovedTo)
+    } else if mask.contains(EventMask::CLOSE_WRITE) {
+        Some(CreationKind::ClosedWrite)
+    } else if mask.contains(EventMask::CREATE) {
+        Some(CreationKind::Created)
+    } else if mask.contains(EventMask::MODIFY) {
+        Some(CreationKind::Modified)
+    } else if mask.contains(EventMask::ATTRIB) {
+        Some(CreationKind::AttributesChanged)
+    } else if mask.intersects(EventMask::DELETE | EventMask::MOVED_FROM) {
+        Some(CreationKind::Deleted)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn overflow() {
+        use crate::{Change, ChangeSource};
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let options = crate::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone());
+        let mut watcher = super::DirWatcher::new(&options).unwrap();
+        watcher.watch(&temp_dir).unwrap();
+        let max_events = std::fs::read_to_string("/proc/sys/fs/inotify/max_queued_events")
+            .ok()
+            .and_then(|max| max.trim().parse::<usize>().ok())
+            .unwrap_or(16384);
+        for i in 0..=max_events {
+            std::fs::File::create(temp_dir.join(i.to_string())).unwrap();
+        }
+
+        let mut buffer = [0; 4096];
+        let mut overflow = false;
+        loop {
+            match watcher.read_changes(&mut buffer, |_, change| overflow |= matches!(change, Change::Overflow)) {
+                Ok(()) => (),
+                Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(error) => panic!("failed to read changes: {}", error),
+            }
+        }
+        assert!(overflow);
+    }
+}
@@ -0,0 +1,136 @@
+//! Watching directories using kqueue.
+//!
+//! Unlike inotify kqueue only reports that the directory was modified, not which entry changed
+//! or whether a file was closed after writing.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, RawFd};
+use std::path::Path;
+use std::ptr;
+use crate::{Change, ChangeSource, Options, set_cloexec};
+
+#[cfg(target_os = "macos")]
+const DIR_OPEN_FLAGS: libc::c_int = libc::O_DIRECTORY | libc::O_EVTONLY;
+// O_EVTONLY is macOS-specific, the BSDs need the directory opened for reading
+#[cfg(not(target_os = "macos"))]
+const DIR_OPEN_FLAGS: libc::c_int = libc::O_DIRECTORY;
+
+pub(crate) type WatchId = RawFd;
+
+/// kqueue doesn't report names of changed entries.
+pub(crate) const REPORTS_NAMES: bool = false;
+
+/// kqueue doesn't report closing of files.
+pub(crate) const REPORTS_CLOSE_WRITE: bool = false;
+
+pub(crate) struct DirWatcher {
+    kqueue: File,
+    dirs: Vec<File>,
+}
+
+impl DirWatcher {
+    pub(crate) fn new(options: &Options) -> io::Result<Self> {
+        if options.only_accept_rename {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "kqueue can't tell which entry was renamed"));
+        }
+        // SAFETY: the call has no preconditions
+        let fd = unsafe { libc::kqueue() };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the fd was just created and nothing else owns it
+        let kqueue = unsafe { File::from_raw_fd(fd) };
+        set_cloexec(fd)?;
+
+        Ok(DirWatcher {
+            kqueue,
+            dirs: Vec::new(),
+        })
+    }
+
+    pub(crate) fn watch(&mut self, dir: &Path) -> io::Result<WatchId> {
+        let dir = OpenOptions::new()
+            .read(true)
+            .custom_flags(DIR_OPEN_FLAGS)
+            .open(dir)?;
+
+        // SAFETY: kevent is a plain C struct for which zeroes are valid
+        let mut event: libc::kevent = unsafe { std::mem::zeroed() };
+        event.ident = dir.as_raw_fd() as _;
+        event.filter = libc::EVFILT_VNODE as _;
+        event.flags = (libc::EV_ADD | libc::EV_CLEAR) as _;
+        event.fflags = (libc::NOTE_WRITE | libc::NOTE_DELETE | libc::NOTE_RENAME | libc::NOTE_REVOKE) as _;
+
+        // SAFETY: we pass exactly one valid change and no event buffer
+        let result = unsafe { libc::kevent(self.kqueue.as_raw_fd(), &event, 1, ptr::null_mut(), 0, ptr::null()) };
+        if result == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let id = dir.as_raw_fd();
+        self.dirs.push(dir);
+        Ok(id)
+    }
+
+    pub(crate) fn unwatch(&mut self, watch: WatchId) -> io::Result<()> {
+        // Closing the directory removes it from the kqueue along with its pending events
+        let len = self.dirs.len();
+        self.dirs.retain(|dir| dir.as_raw_fd() != watch);
+        if self.dirs.len() == len {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the directory is not watched"));
+        }
+        Ok(())
+    }
+}
+
+impl ChangeSource for DirWatcher {
+    fn read_changes<F: FnMut(&WatchId, Change<'_>)>(&mut self, _buffer: &mut [u8], mut f: F) -> io::Result<()> {
+        // SAFETY: kevent is a plain C struct for which zeroes are valid
+        let mut events: [libc::kevent; 16] = unsafe { std::mem::zeroed() };
+        let timeout = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+
+        let count = loop {
+            // SAFETY: the pointer and length come from a valid array, zero timeout makes it
+            // non-blocking
+            let result = unsafe { libc::kevent(self.kqueue.as_raw_fd(), ptr::null(), 0, events.as_mut_ptr(), events.len() as _, &timeout) };
+            if result != -1 {
+                break result as usize;
+            }
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        };
+
+        if count == 0 {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        for event in &events[..count] {
+            let id = event.ident as RawFd;
+            if event.fflags & (libc::NOTE_DELETE | libc::NOTE_RENAME | libc::NOTE_REVOKE) != 0 {
+                f(&id, Change::Removed);
+            } else {
+                f(&id, Change::Unknown);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AsRawFd for DirWatcher {
+    fn as_raw_fd(&self) -> RawFd {
+        self.kqueue.as_raw_fd()
+    }
+}
+
+impl AsFd for DirWatcher {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.kqueue.as_fd()
+    }
+}
@@ -5,9 +5,10 @@
 //! provides a very simple API. See the shorthand functions provided in this crate first - you
 //! likely only need one of them.
 //!
-//! It uses `inotify` which is available in Linux only to wait for the file. `notify` crate was
-//! specifically not used to ensure high robustness. PRs to add other platforms will be accepted if
-//! I can not see race conditions or other bugs in them.
+//! It uses `inotify` on Linux, `kqueue` on macOS and the BSDs and `ReadDirectoryChangesW` on
+//! Windows to wait for the file. `notify` crate was specifically not used to ensure high
+//! robustness. PRs to add other platforms will be
+//! accepted if I can not see race conditions or other bugs in them.
 //!
 //! ## Example
 //!
@@ -42,13 +43,95 @@
 //! file descriptor is *closed* even though it would be safe to open it after it was *created*.
 //! The `assume_create_is_atomic()` method can be used to indicate that and request the file to be
 //! opened right away. This may improve performance or in case the application wants to keep the
-//! file descriptor opened it ensures the code functions at all. The same method can be used if the
-//! file is supposed to be empty.
+//! file descriptor opened it ensures the code functions at all. The `wait_for_linked_tmpfile()`
+//! function is a shorthand for this case. If the file is intentionally empty use
+//! `allow_empty_file()` instead so that it's returned when the writer closes it even if waiting for
+//! its contents was requested.
+//!
+//! `kqueue` only reports that the directory changed, not that a file was closed after writing.
+//! Because of this on macOS and the BSDs the file is opened as soon as it appears in the directory,
+//! just as if `assume_create_is_atomic()` was used. Producers should create the file atomically
+//! there. The same applies to Windows where `ReadDirectoryChangesW` doesn't report closing files
+//! either.
 
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+#[cfg(unix)]
+use std::ffi::CStr;
+use std::ffi::OsStr;
+use std::fmt;
 use std::fs::{File, OpenOptions};
-use std::path::Path;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsRawHandle, OwnedHandle};
+use std::path::{Path, PathBuf};
 use std::io;
-use std::time::Duration;
+use std::ops::ControlFlow;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Emits a `tracing` event if the feature is enabled, expands to nothing otherwise.
+macro_rules! trace_event {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($arg)*);
+    };
+}
+
+#[cfg(feature = "async-io")]
+mod async_io_support;
+mod clock;
+#[cfg(feature = "serde")]
+mod config;
+mod deletion;
+mod directory_waiter;
+mod error;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod inotify_backend;
+#[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "netbsd", target_os = "openbsd", target_os = "dragonfly"))]
+mod kqueue_backend;
+#[cfg(windows)]
+mod windows_backend;
+#[cfg(unix)]
+mod lock;
+mod multi;
+mod pattern;
+mod recursive;
+mod shared;
+mod state;
+#[cfg(feature = "tokio")]
+mod tokio_support;
+mod watch_directory;
+mod watcher;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use inotify_backend as backend;
+#[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "netbsd", target_os = "openbsd", target_os = "dragonfly"))]
+use kqueue_backend as backend;
+#[cfg(windows)]
+use windows_backend as backend;
+
+// Without a backend the errors about missing items would hide the actual cause
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "freebsd", target_os = "netbsd", target_os = "openbsd", target_os = "dragonfly", windows)))]
+compile_error!("wait_file_created supports only Linux, Android, macOS, FreeBSD, NetBSD, OpenBSD, DragonFly BSD and Windows");
+
+pub use clock::{Clock, SystemClock};
+#[cfg(feature = "serde")]
+pub use config::OptionsConfig;
+pub use directory_waiter::DirectoryWaiter;
+pub use error::WaitError;
+pub use shared::SharedWaiter;
+pub use state::{Notification, OpenResult, Polling, WaitAction, WaitConfig, WaitOutcome, WaitState};
+#[cfg(unix)]
+pub use lock::LockType;
+pub use watch_directory::DirectoryWatch;
+pub use watcher::Watcher;
+/// Re-exported to make it easier to use the same version in `open_when_created_with_inotify`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use inotify;
 
 /// Builder allowing configuration beyond what shorthand functions enable.
 ///
@@ -58,11 +141,50 @@ use std::time::Duration;
 ///
 /// Pay attention to how default values of this builder differ from those in shorthand functions!
 /// Also make sure you understand the implications of the settings.
+///
+/// The builder is cheap to clone so it's possible to prepare a template and tweak it for each
+/// call.
+#[derive(Clone, Debug)]
 pub struct Options {
     open_options: OpenOptions,
+    opener: Option<Opener>,
     retry_flukes: bool,
+    max_fluke_retries: Option<usize>,
+    fluke_cooldown: Option<Duration>,
+    retry_permission_denied: bool,
+    retry_on_error: Option<ErrorFilter>,
     create_is_atomic: bool,
-    polling_fallback: Option<Duration>,
+    only_accept_rename: bool,
+    wait_for_nonempty: bool,
+    min_size: u64,
+    allow_empty: bool,
+    prefix: Option<Arc<[u8]>>,
+    quiescence: Option<Duration>,
+    expect_directory: bool,
+    #[cfg(unix)]
+    file_type: FileType,
+    follow_symlinks: bool,
+    wait_for_parent: bool,
+    watch_dir: Option<PathBuf>,
+    canonicalize_parent: bool,
+    parent_removed: ParentRemovedPolicy,
+    max_watch_recreations: Option<usize>,
+    polling_fallback: Option<Polling>,
+    polling_only: bool,
+    recheck_interval: Option<Duration>,
+    event_buffer_size: Option<usize>,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    clock: Option<Arc<dyn Clock>>,
+    heartbeat: Option<Heartbeat>,
+    // Set internally when waiting for the file to be deleted
+    watch_deletions: bool,
+    // Set internally when watching new directories in a tree
+    watch_creations: bool,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    on_event: Option<inotify_backend::EventHook>,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    extra_watch_mask: inotify::WatchMask,
 }
 
 impl Options {
@@ -71,13 +193,56 @@ impl Options {
     /// This crates configuration with **no** robustness settings by default.
     /// That means `retry_on_fluke` is `false` and there is no polling fallback.
     /// *Creation* of the file is **not** assumed to be atomic.
+    /// There is no timeout - waiting may block forever.
     /// You must add them explicitly using the builder methods!
+    ///
+    /// Note that if `open_options` has `create(true)` set the file is created by the first open
+    /// attempt if it doesn't exist so no waiting happens at all. This is only useful if you want
+    /// to create the file if you win the race with the other application.
+    ///
+    /// Custom flags set using `OpenOptionsExt::custom_flags` are used for opening the file, except
+    /// when `follow_symlinks(false)` or `expect_file_type(FileType::Fifo)` needs flags of its own.
+    /// Note that `O_NONBLOCK` changes the semantics of opening FIFOs: opening it for reading
+    /// doesn't wait for a writer and opening it write-only without a reader fails with `ENXIO`.
     pub fn with_open_options(open_options: OpenOptions) -> Self {
         Options {
             open_options,
+            opener: None,
             retry_flukes: false,
+            max_fluke_retries: None,
+            fluke_cooldown: None,
+            retry_permission_denied: false,
+            retry_on_error: None,
             create_is_atomic: false,
+            only_accept_rename: false,
+            wait_for_nonempty: false,
+            min_size: 0,
+            allow_empty: false,
+            prefix: None,
+            quiescence: None,
+            expect_directory: false,
+            #[cfg(unix)]
+            file_type: FileType::Any,
+            follow_symlinks: true,
+            wait_for_parent: false,
+            watch_dir: None,
+            canonicalize_parent: false,
+            parent_removed: ParentRemovedPolicy::FallBackToPolling,
+            max_watch_recreations: None,
             polling_fallback: None,
+            polling_only: false,
+            recheck_interval: None,
+            event_buffer_size: None,
+            timeout: None,
+            deadline: None,
+            clock: None,
+            heartbeat: None,
+            watch_deletions: false,
+            watch_creations: false,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            on_event: None,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            extra_watch_mask: inotify::WatchMask::empty(),
         }
     }
 
@@ -92,6 +257,62 @@ impl Options {
         self
     }
 
+    /// Limits how many times a fluke is retried.
+    ///
+    /// A buggy or malicious application could keep creating and deleting the file forever. If
+    /// `retry_on_fluke` is `true` this limits the number of retries after which waiting fails
+    /// with `WaitError::FlukeRetriesExhausted`. This has no effect if `retry_on_fluke` is `false`.
+    ///
+    /// By default the number of retries is unlimited.
+    pub fn max_fluke_retries(mut self, max_retries: usize) -> Self {
+        self.max_fluke_retries = Some(max_retries);
+        self
+    }
+
+    /// Sleeps for `cooldown` after a fluke before waiting again.
+    ///
+    /// If the other application keeps creating and deleting the file quickly retrying right away
+    /// would spin opening the file over and over. The events received while sleeping are not lost,
+    /// they are processed afterwards. The cooldown counts towards the timeout and each fluke still
+    /// counts towards `max_fluke_retries`. This has no effect if `retry_on_fluke` is `false`.
+    ///
+    /// The cooldown applies to all blocking and asynchronous waits but not to `Watcher` which
+    /// never blocks.
+    pub fn fluke_cooldown(mut self, cooldown: Duration) -> Self {
+        self.fluke_cooldown = Some(cooldown);
+        self
+    }
+
+    /// Keeps waiting if opening the file fails because of insufficient permissions.
+    ///
+    /// Some applications create the file with restrictive permissions and only make it accessible
+    /// once it's ready. If this is set to `true` failing to open the file with
+    /// `ErrorKind::PermissionDenied` means the file is not ready yet and it's opened again after
+    /// its attributes change.
+    ///
+    /// Note that `kqueue` doesn't report permission changes so on macOS and the BSDs this should be
+    /// combined with `inotify_with_polling`. Entries yielded by `watch_directory` are not re-checked
+    /// after their permissions change.
+    pub fn retry_on_permission_denied(mut self, retry: bool) -> Self {
+        self.retry_permission_denied = retry;
+        self
+    }
+
+    /// Keeps waiting if opening the file fails with an error accepted by `retry`.
+    ///
+    /// By default only `ErrorKind::NotFound` means the file is not ready yet and all other errors
+    /// are returned right away. Some file systems, e.g. network ones, report transient errors such
+    /// as `EBUSY` so if `retry` returns `true` for the error the file is opened again later
+    /// instead. `ErrorKind::NotFound` is always retried so it's not passed to `retry`.
+    ///
+    /// The file is opened again after the next change in the directory which may never come if
+    /// the error isn't caused by a change of the file. Combine this with `inotify_with_polling` to
+    /// retry periodically.
+    pub fn retry_on_error<F: Fn(&io::Error) -> bool + Send + Sync + 'static>(mut self, retry: F) -> Self {
+        self.retry_on_error = Some(ErrorFilter(Arc::new(retry)));
+        self
+    }
+
     /// Fallback to polling if inotify calls fail for any reason.
     ///
     /// If any inotify syscall fails it could be that the file may still be opened.
@@ -100,10 +321,115 @@ impl Options {
     ///
     /// Note that by default shorthand functions in this library use 2 second interval.
     pub fn polling_fallback_interval(mut self, interval: Duration) -> Self {
-        self.polling_fallback = Some(interval);
+        self.polling_fallback = Some(Polling::constant(interval));
+        self
+    }
+
+    /// Fallback to polling with exponentially growing intervals.
+    ///
+    /// This works like `polling_fallback_interval` except the first sleep lasts `initial` and each
+    /// following one is `factor` times longer, up to `max`. The delay is reset back to `initial`
+    /// whenever the file is found to exist but isn't ready yet.
+    ///
+    /// `initial` greater than `max` is clamped to `max`.
+    ///
+    /// # Panics
+    ///
+    /// This panics if `factor` is not finite or is less than `1.0`.
+    pub fn polling_fallback_backoff(mut self, initial: Duration, max: Duration, factor: f64) -> Self {
+        self.polling_fallback = Some(Polling::new(initial, max, factor));
+        self
+    }
+
+    /// Polls for the file using the given interval without attempting to use inotify at all.
+    ///
+    /// This is useful in environments where inotify is known to be unavailable, e.g. when it's
+    /// disabled by seccomp, to avoid the overhead of failing syscalls on every call. The interval
+    /// is required since there would be nothing to fall back to otherwise.
+    pub fn polling_only(mut self, interval: Duration) -> Self {
+        self.polling_fallback = Some(Polling::constant(interval));
+        self.polling_only = true;
+        self
+    }
+
+    /// Re-checks the file periodically even if inotify doesn't report any change.
+    ///
+    /// Events may be lost, e.g. when the inotify queue overflows. If this is set the file is
+    /// attempted to be opened every `interval` in addition to attempts after events so even lost
+    /// events can only delay opening the file, not prevent it.
+    pub fn inotify_with_polling(mut self, interval: Duration) -> Self {
+        self.recheck_interval = Some(interval);
+        self
+    }
+
+    /// Sets the size of the buffer for reading inotify events.
+    ///
+    /// By default a 4096 B buffer on the stack is used. In busy directories a larger buffer allows
+    /// reading more events with a single syscall. The buffer is allocated on the heap for each wait.
+    ///
+    /// Returns an error with `ErrorKind::InvalidInput` if the size is too small to hold a single
+    /// event with the longest possible file name. The size has no effect on other platforms.
+    pub fn event_buffer_size(mut self, size: usize) -> io::Result<Self> {
+        if size < MIN_EVENT_BUFFER_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("the event buffer must have at least {} bytes", MIN_EVENT_BUFFER_SIZE)));
+        }
+        self.event_buffer_size = Some(size);
+        Ok(self)
+    }
+
+    /// Limits the total time spent waiting for the file.
+    ///
+    /// The timeout covers the whole operation including waiting for inotify events and sleeping
+    /// between polling attempts. If the file couldn't be opened before the timeout elapses an
+    /// error with `ErrorKind::TimedOut` is returned.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Stops waiting for the file at the given point in time.
+    ///
+    /// This is the same as `timeout` except the time is absolute which is convenient when a single
+    /// deadline is shared by multiple consecutive waits. If both are set the one expiring earlier
+    /// applies. The deadline is compared with the time of the clock set by `with_clock`.
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Sets the clock used for timeouts and sleeping between polling attempts.
+    ///
+    /// The real clock is used by default. This is mainly useful for testing code using timeouts
+    /// or polling without waiting for real time to pass. The async functions ignore the clock and
+    /// use the timer of their runtime (`tokio` or `async-io`) instead.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
         self
     }
 
+    /// Calls `callback` roughly every `interval` while waiting.
+    ///
+    /// This provides a liveness signal during long waits, e.g. for logging progress, without
+    /// checking the filesystem more often. The callback is called between waiting for events so
+    /// it doesn't delay handling them and it's not called anymore once the file is opened. The
+    /// first call happens `interval` after waiting started.
+    ///
+    /// Only the blocking methods call the callback, the async ones can use a timer of the runtime
+    /// instead.
+    ///
+    /// Returns an error with `ErrorKind::InvalidInput` if `interval` is zero since the callback
+    /// would be called in a busy loop.
+    pub fn heartbeat<F: FnMut() + Send + 'static>(mut self, interval: Duration, callback: F) -> io::Result<Self> {
+        if interval.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the heartbeat interval must not be zero"));
+        }
+        self.heartbeat = Some(Heartbeat {
+            interval,
+            callback: Arc::new(Mutex::new(callback)),
+        });
+        Ok(self)
+    }
+
     /// Indicates that file creation is atomic and you want the file to be opened right away.
     ///
     /// Some applications may create a file atomically and then keep the file descriptor around.
@@ -112,171 +438,3825 @@ impl Options {
     /// creation you should indicate so using this method so that an earlier notification is used
     /// to determine when to open the file.
     ///
-    /// You can learm more about this in the `Limitations` section of the crate documentation
+    /// You can learm more about this in the `Limitations` section of the crate documentation.
+    /// Files which are intentionally empty don't need this, see `allow_empty_file`.
     pub fn assume_create_is_atomic(mut self, is_atomic: bool) -> Self {
         self.create_is_atomic = is_atomic;
         self
     }
 
+    /// Opens the file only after it was renamed into place.
+    ///
+    /// Well-behaved producers write the file under a temporary name and then rename it so once it
+    /// appears under the final name it's complete. If this is set to `true` creating the file or
+    /// closing it after writing doesn't make it available, only renaming it does. A file which
+    /// already exists when waiting starts is not accepted either since it's unknown how it got
+    /// there, waiting continues until another file is renamed over it.
+    ///
+    /// Renames can not be told apart from other changes by polling so instead of falling back to
+    /// it waiting fails with the error of the watch. This is not supported on macOS and the BSDs
+    /// since `kqueue` doesn't report the names of the changed entries.
+    pub fn only_accept_rename(mut self, only_rename: bool) -> Self {
+        self.only_accept_rename = only_rename;
+        self
+    }
+
+    /// Keeps waiting if the opened file is empty.
+    ///
+    /// Some applications create the file first and write to it later without using atomic
+    /// creation. If this is set to `true` and the file is empty after opening it waiting continues
+    /// until the file is modified or closed by the writer and it's non-empty.
+    ///
+    /// This is only a best-effort heuristic. The file may still be incomplete if the writer
+    /// writes in multiple steps. On kqueue-based platforms modifications of the file itself are
+    /// not reported so waiting only continues after the directory changes again (or the next
+    /// polling attempt).
+    pub fn wait_for_nonempty(mut self, wait: bool) -> Self {
+        self.wait_for_nonempty = wait;
+        self
+    }
+
+    /// Keeps waiting until the opened file has at least `min_size` bytes.
+    ///
+    /// This extends `wait_for_nonempty` for cases when the size of the data (or at least of its
+    /// header) is known upfront. The same limitations apply.
+    ///
+    /// If the writer closes the file before it reaches the size the file is returned anyway since
+    /// the writer is most likely done. The only exception is an empty file which may be created
+    /// and closed before the data is written into it unless `allow_empty_file` is set. Check the
+    /// length of the returned file if you need to detect this.
+    pub fn wait_for_min_size(mut self, min_size: u64) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Accepts the file being empty once the writer closes it.
+    ///
+    /// Unlike `assume_create_is_atomic`, which changes when the file is opened, this only changes
+    /// what counts as a finished file. By default an empty file closed by the writer is considered
+    /// unfinished if `wait_for_nonempty` or `wait_for_min_size` is set since some writers create
+    /// and close the file before writing the data. Setting this to `true` returns such file so
+    /// that intentionally empty files, e.g. markers, don't block waiting forever while files
+    /// which are still open for writing are still waited for.
+    ///
+    /// This has no effect if the size of the file is not checked. Note that closing the file is
+    /// not reported by kqueue and `ReadDirectoryChangesW`.
+    pub fn allow_empty_file(mut self, allow: bool) -> Self {
+        self.allow_empty = allow;
+        self
+    }
+
+    /// Keeps waiting until the opened file starts with `prefix`.
+    ///
+    /// This is useful for formats with a known magic header such as PNG or gzip. The beginning
+    /// of the file is read after opening it and if it doesn't match the file is checked again
+    /// after it's modified. The returned file is positioned at the start.
+    ///
+    /// This is only a heuristic - the file starting with the right bytes doesn't mean it was
+    /// written completely. The file must be opened for reading and it must be seekable. Unlike
+    /// `wait_for_min_size` the file is not returned if the writer closes it with a different
+    /// prefix since that would make the check pointless. On kqueue-based platforms the same
+    /// limitation as in `wait_for_nonempty` applies.
+    pub fn wait_for_prefix(mut self, prefix: &[u8]) -> Self {
+        self.prefix = if prefix.is_empty() { None } else { Some(prefix.into()) };
+        self
+    }
+
+    /// Sets the permission bits used if the file is created by opening it.
+    ///
+    /// This is a shorthand for `OpenOptionsExt::mode` on the `OpenOptions` given to the builder
+    /// and it only has an effect if they have `create(true)` set. See `with_open_options` for
+    /// implications of creating the file.
+    #[cfg(unix)]
+    pub fn create_mode(mut self, mode: u32) -> Self {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        self.open_options.mode(mode);
+        self
+    }
+
+    /// Opens the file using `opener` instead of the `OpenOptions` given to the builder.
+    ///
+    /// This is useful if files have to be opened through a wrapper, e.g. for sandboxing, or to
+    /// test code using this crate without touching the file system. The opener is called at each
+    /// attempt to open the file and `ErrorKind::NotFound` means the file doesn't exist yet. The
+    /// file it returns is then checked according to the other options, e.g. its size.
+    ///
+    /// The opener is responsible for honoring `follow_symlinks(false)` and for opening FIFOs in
+    /// non-blocking mode. It's not used if `expect_directory` is set.
+    pub fn with_opener<F: Fn(&Path) -> io::Result<File> + Send + Sync + 'static>(mut self, opener: F) -> Self {
+        self.opener = Some(Opener(Arc::new(opener)));
+        self
+    }
+
+    /// Waits until the file wasn't modified for the given duration after opening it.
+    ///
+    /// This is intended for large files written non-atomically. After the file is opened
+    /// modification events are watched and each of them restarts the quiet period. The file is
+    /// returned only once the whole period passes without modifications. If events can not be
+    /// used (polling, multiple files or async) the size and modification time of the file are
+    /// compared instead.
+    ///
+    /// This is only a heuristic! A writer which pauses for longer than the period will still
+    /// produce incomplete data. On kqueue-based platforms modifications of the file itself are not
+    /// reported so any change in the directory restarts the period instead. The timeout includes
+    /// the quiet period.
+    pub fn wait_for_quiescence(mut self, quiet: Duration) -> Self {
+        self.quiescence = Some(quiet);
+        self
+    }
+
+    /// Waits for a directory instead of a regular file.
+    ///
+    /// If this is set to `true` waiting ends once a directory is created at the path and it's an
+    /// error if anything else appears there. Creation of directories is atomic so waiting ends
+    /// right after it's created. `open_when_created` then returns the directory opened read-only
+    /// ignoring the `OpenOptions` given to the builder.
+    pub fn expect_directory(mut self, expect: bool) -> Self {
+        self.expect_directory = expect;
+        self
+    }
+
+    /// Only opens the file if it has the given type, otherwise waiting continues.
+    ///
+    /// The type is checked before opening so that e.g. a FIFO appearing instead of a regular file
+    /// doesn't block the open, and again after opening in case the file was replaced in between.
+    ///
+    /// When expecting a FIFO it's opened with `O_NONBLOCK` since opening it would otherwise block
+    /// until the other end is opened. The returned file stays non-blocking. Opening a FIFO
+    /// write-only without a reader fails with `ENXIO`. Sockets can not be opened at all so use
+    /// `wait_until_created` to wait for them. Same as in `follow_symlinks`, `O_NONBLOCK` replaces
+    /// any custom flags set in the `OpenOptions` so include it in them if you need other ones.
+    #[cfg(unix)]
+    pub fn expect_file_type(mut self, file_type: FileType) -> Self {
+        self.file_type = file_type;
+        self
+    }
+
+    /// Tells whether a symlink at the path should be followed when opening the file.
+    ///
+    /// If this is set to `false` the file is opened with `O_NOFOLLOW` so if the path is a symlink
+    /// opening it fails with `ELOOP` instead of opening the file it points to. This prevents being
+    /// redirected to an attacker-controlled file in world-writable directories. Note that
+    /// `O_NOFOLLOW` replaces any custom flags set in the `OpenOptions` given to the builder so
+    /// include it in them instead if you need other custom flags.
+    ///
+    /// Only the last component of the path is affected. Symlinks in the parent directories are
+    /// always followed, including when watching the parent directory. The default is `true`.
+    pub fn follow_symlinks(mut self, follow: bool) -> Self {
+        self.follow_symlinks = follow;
+        self
+    }
+
+    /// Waits for the parent directories to be created if they don't exist.
+    ///
+    /// Only an existing directory can be watched so by default waiting fails (or falls back to
+    /// polling) if the parent directory is missing. If this is set to `true` the nearest existing
+    /// ancestor is watched instead and each missing directory is waited for in turn until the
+    /// parent of the file exists. The timeout covers waiting for the directories as well.
+    ///
+    /// This is only supported by the blocking methods waiting for a single file.
+    pub fn wait_for_parent(mut self, wait: bool) -> Self {
+        self.wait_for_parent = wait;
+        self
+    }
+
+    /// Watches `dir` instead of the parent directory of the file.
+    ///
+    /// The events may surface on a different path than the one used to open the file, e.g. when
+    /// the file is accessed through a bind mount or an overlay but created through the underlying
+    /// directory. The watch is then placed on `dir` while the file is still opened using the
+    /// original path. The events are matched using the file name of the original path so the file
+    /// must have the same name in both directories.
+    ///
+    /// This is only supported by the methods waiting for a single file.
+    pub fn watch_dir_override(mut self, dir: PathBuf) -> Self {
+        self.watch_dir = Some(dir);
+        self
+    }
+
+    /// Canonicalizes the parent directory before watching it.
+    ///
+    /// Paths containing `..` or symlinks may make the watched directory differ from the one the
+    /// file is opened in, e.g. `a/../b/file` when `a` is a symlink. If this is set to `true` the
+    /// watched directory is resolved the same way the kernel resolves it when opening the file.
+    /// The file itself is still opened using the original path. The directory set by
+    /// `watch_dir_override` is canonicalized as well. The default is `false`.
+    ///
+    /// Canonicalization requires the directory to exist so waiting fails with
+    /// `WaitError::AddWatch` if it doesn't, even if `wait_for_parent` is set.
+    pub fn canonicalize_parent(mut self, canonicalize: bool) -> Self {
+        self.canonicalize_parent = canonicalize;
+        self
+    }
+
+    /// Chooses what to do if the parent directory is removed while waiting.
+    ///
+    /// The watch can't observe the directory once it's removed. By default waiting falls back to
+    /// polling or fails with `WaitError::ReadEvents` if polling is disabled. See
+    /// `ParentRemovedPolicy` for the alternatives.
+    ///
+    /// This is only supported by the blocking methods waiting for a single file.
+    pub fn on_parent_removed(mut self, policy: ParentRemovedPolicy) -> Self {
+        self.parent_removed = policy;
+        self
+    }
+
+    /// Recreates the watch at most `max_attempts` times if the parent directory is removed.
+    ///
+    /// This is the same as `on_parent_removed(ParentRemovedPolicy::RecreateWatch)` except the
+    /// number of times the directory may be removed is limited. Each time it's removed waiting
+    /// continues until it exists again, then it's watched and the file is checked again. Once the
+    /// limit is exceeded `WaitError::ParentRemoved` is returned. This is useful for directories
+    /// which are deleted and recreated e.g. during deployments while still failing if something
+    /// keeps removing them.
+    ///
+    /// This is only supported by the blocking methods waiting for a single file.
+    pub fn recreate_watch_on_ignored(mut self, max_attempts: usize) -> Self {
+        self.parent_removed = ParentRemovedPolicy::RecreateWatch;
+        self.max_watch_recreations = Some(max_attempts);
+        self
+    }
+
+    /// Calls `callback` for each raw inotify event received while waiting.
+    ///
+    /// This is intended for observability - e.g. counting spurious events or finding out why
+    /// waiting takes long. The callback is called before the event is interpreted and it can not
+    /// influence the waiting in any way. It's shared between clones of the builder.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn on_event<F: FnMut(&inotify::Event<&OsStr>) + Send + 'static>(mut self, callback: F) -> Self {
+        self.on_event = Some(inotify_backend::EventHook::new(callback));
+        self
+    }
+
+    /// Adds flags to the mask of the inotify watch of the parent directory.
+    ///
+    /// The flags are combined with those required by the other settings. This gives access to
+    /// inotify features not covered by the builder.
+    ///
+    /// These flags are safe to add:
+    ///
+    /// * `DONT_FOLLOW` - fails to watch the parent if it's a symlink
+    /// * `EXCL_UNLINK` - ignores events of files unlinked from the directory
+    /// * any additional events - they cause spurious wakeups but nothing else
+    ///
+    /// These flags break waiting and must **not** be used:
+    ///
+    /// * `ONESHOT` - the events after the first one are missed
+    /// * `MASK_CREATE` - the watch fails if the directory is already watched by the same instance,
+    ///   e.g. in `open_when_created_with_inotify`
+    /// * `MASK_ADD` - can keep flags of an earlier watch in the same instance, at best useless
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn extra_watch_mask(mut self, mask: inotify::WatchMask) -> Self {
+        self.extra_watch_mask = mask;
+        self
+    }
+
     /// Opens the file once it's available waiting for it to be created if it doesn't exist yet.
     ///
     /// This method uses settings specified in the builder to wait for file to be created and open
     /// it.
+    ///
+    /// File names longer than 255 bytes (UTF-16 code units on Windows) can never be created so an
+    /// error with `ErrorKind::InvalidInput` is returned right away instead of waiting forever.
     #[inline]
     pub fn open_when_created<P: AsRef<Path>>(&self, path: P) -> io::Result<File> {
         // Monomorphise the method to avoid machine code blowup
-        self.internal_open_when_created(path.as_ref())
+        self.internal_open_when_created(path.as_ref(), None).map(|(file, _)| file).map_err(Into::into)
     }
 
-    fn internal_open_when_created(&self, path: &Path) -> io::Result<File> {
-        use inotify::WatchMask;
-
-        match inotify::Inotify::init() {
-            Ok(mut inotify) => {
-                let mut mask = WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::DELETE_SELF | WatchMask::ONLYDIR;
-                if self.create_is_atomic {
-                    mask |= WatchMask::CREATE;
-                }
+    /// Opens the file once it's available reporting detailed errors.
+    ///
+    /// This behaves the same as `open_when_created` but the returned error tells at which stage
+    /// waiting failed.
+    #[inline]
+    pub fn try_open_when_created<P: AsRef<Path>>(&self, path: P) -> Result<File, WaitError> {
+        self.internal_open_when_created(path.as_ref(), None).map(|(file, _)| file)
+    }
 
-                match inotify.add_watch(path, mask) {
-                    Ok(_) => (),
-                    Err(error) => return self.try_fallback_open(path, error),
-                };
+    /// Opens the file given by a C string once it's available.
+    ///
+    /// This behaves the same as `open_when_created` but it's convenient when the path was built
+    /// as `CString`, e.g. by FFI code. The bytes are used as they are without UTF-8 validation or
+    /// copying them into `PathBuf` so any name the OS accepts works. Note that `&OsStr` can be
+    /// passed to `open_when_created` directly without allocating.
+    #[cfg(unix)]
+    #[inline]
+    pub fn open_when_created_cstr(&self, path: &CStr) -> io::Result<File> {
+        use std::os::unix::ffi::OsStrExt;
 
-                self.wait_for_file(inotify, path)
+        self.open_when_created(OsStr::from_bytes(path.to_bytes()))
+    }
 
-            },
-            Err(error) => self.try_fallback_open(path, error),
-        }
+    /// Opens the file once it's available returning the owned file descriptor.
+    ///
+    /// This is useful for passing the descriptor to FFI code taking the ownership without
+    /// converting `File` manually.
+    #[cfg(unix)]
+    #[inline]
+    pub fn open_when_created_owned_fd<P: AsRef<Path>>(&self, path: P) -> io::Result<OwnedFd> {
+        self.open_when_created(path).map(OwnedFd::from)
     }
 
-    fn try_fallback_open(&self, path: &Path, inotify_error: io::Error) -> io::Result<File> {
-        loop {
-            match self.open_options.open(path) {
-                Ok(file) => return Ok(file),
-                Err(error) if error.kind() == io::ErrorKind::NotFound => (),
-                Err(error) => return Err(error),
-            }
+    /// Opens the file once it's available and reports how long waiting took.
+    ///
+    /// The duration covers the whole call including setting up the watch and sleeping between
+    /// polling attempts. This is useful for monitoring latency of the application creating the
+    /// file.
+    pub fn open_when_created_timed<P: AsRef<Path>>(&self, path: P) -> io::Result<(File, Duration)> {
+        let start = self.clock().now();
+        self.open_when_created(path).map(|file| (file, self.clock().now().saturating_duration_since(start)))
+    }
 
-            match &self.polling_fallback {
-                Some(interval) => std::thread::sleep(*interval),
-                None => return Err(inotify_error),
-            }
-        }
+    /// Opens the file once it's available and returns its metadata.
+    ///
+    /// The metadata is obtained from the opened file rather than the path so it always describes
+    /// the file that was opened even if the path was replaced in the meantime. This allows e.g.
+    /// comparing the inode with an older handle to detect that the file was atomically replaced.
+    pub fn open_when_created_with_metadata<P: AsRef<Path>>(&self, path: P) -> io::Result<(File, std::fs::Metadata)> {
+        let file = self.open_when_created(path)?;
+        let metadata = file.metadata()?;
+        Ok((file, metadata))
     }
 
-    fn wait_for_file(&self, mut inotify: inotify::Inotify, path: &Path) -> io::Result<File> {
-        use inotify::EventMask;
+    /// Opens the file once it's available and reads all of it.
+    ///
+    /// The buffer is allocated upfront using the size of the opened file which avoids
+    /// reallocations when reading big files. This works best with `wait_for_min_size` or
+    /// `wait_for_nonempty` ensuring the file is complete when it's opened. Data appended after
+    /// opening is still read, it just may need reallocating the buffer.
+    ///
+    /// The open options must allow reading.
+    pub fn wait_read_to_vec_sized<P: AsRef<Path>>(&self, path: P) -> io::Result<Vec<u8>> {
+        use std::convert::TryFrom;
+        use std::io::Read;
 
-        let mut buffer = [0; 4096];
-        let mut not_found_is_ok = true;
+        let (mut file, metadata) = self.open_when_created_with_metadata(path)?;
+        // Files bigger than the address space can't be read whole anyway
+        let mut contents = Vec::with_capacity(usize::try_from(metadata.len()).unwrap_or(0));
+        file.read_to_end(&mut contents)?;
+        Ok(contents)
+    }
 
-        loop {
-            match self.open_options.open(path) {
-                Ok(file) => return Ok(file),
-                Err(error) if error.kind() == io::ErrorKind::NotFound && not_found_is_ok => (),
-                Err(error) => return Err(error),
-            }
+    /// Opens the file once it's available and reports which event made it available.
+    ///
+    /// This behaves the same as `open_when_created` but it also returns the kind of the event
+    /// after which the file was opened. This is mainly useful for debugging to find out how the
+    /// application creating the file actually wrote it.
+    #[inline]
+    pub fn open_when_created_with_event<P: AsRef<Path>>(&self, path: P) -> io::Result<(File, CreationKind)> {
+        self.internal_open_when_created(path.as_ref(), None).map_err(Into::into)
+    }
 
-            #[cfg(all(test, test_delay_after_check))]
-            {
-                // We want to make sure we DO receive notification after we checked existence of
-                // the file even though we didn't ACTIVELY wait for it yet.
-                std::thread::sleep(std::time::Duration::from_secs(7));
-            }
+    /// Opens the file once it's available and reports whether the polling fallback was needed.
+    ///
+    /// Falling back to polling usually means that the system is misconfigured, e.g. the limit of
+    /// inotify watches is too low, or the application runs in a restricted environment. This
+    /// allows monitoring how often it happens.
+    pub fn open_when_created_with_mechanism<P: AsRef<Path>>(&self, path: P) -> io::Result<(File, Mechanism)> {
+        let context = self.wait_context(None);
+        let (file, _) = self.wait_with_context(path.as_ref(), &context, &|path, kind| self.attempt_open(path, kind))?;
+        Ok((file, context.mechanism.get()))
+    }
 
-            let mut found = false;
-            while !found {
-                let events = match inotify.read_events_blocking(&mut buffer) {
-                    Ok(events) => events,
-                    Err(error) => return self.try_fallback_open(path, error),
-                };
+    /// Opens the file once it's available and reports whether it was already there.
+    ///
+    /// The returned `bool` is `true` if the file was opened right away, either before setting up
+    /// the watch or by the first attempt after it, without waiting for any event or polling. It's
+    /// `false` if the file had to be waited for. A file created right before the watch was set up
+    /// is still reported as already present since the two cases can not be told apart.
+    pub fn open_when_created_reporting_preexisting<P: AsRef<Path>>(&self, path: P) -> io::Result<(File, bool)> {
+        let context = self.wait_context(None);
+        let (file, _) = self.wait_with_context(path.as_ref(), &context, &|path, kind| self.attempt_open(path, kind))?;
+        Ok((file, !context.progress.get().missed_open))
+    }
 
-                for event in events {
-                    if event.mask.contains(EventMask::IGNORED) {
-                        return self.try_fallback_open(path, io::Error::from(io::ErrorKind::NotFound));
-                    }
-                    if event.name == Some(path.as_os_str()) {
-                        found = true;
-                    }
-                }
-            }
+    /// Opens the file once it's available unless waiting is cancelled.
+    ///
+    /// This behaves the same as `open_when_created` but returns an error with
+    /// `ErrorKind::Interrupted` as soon as `cancel()` is called on the `token`. This includes
+    /// sleeping between attempts of the polling fallback.
+    #[inline]
+    pub fn open_when_created_cancellable<P: AsRef<Path>>(&self, path: P, token: &CancellationToken) -> io::Result<File> {
+        self.internal_open_when_created(path.as_ref(), Some(token)).map(|(file, _)| file).map_err(Into::into)
+    }
 
-            not_found_is_ok = self.retry_flukes;
+    /// Opens the file once it's available calling `tick` roughly every `interval` while waiting.
+    ///
+    /// This allows single-threaded applications without an async runtime to interleave their own
+    /// work with waiting. Instead of blocking until an event arrives waiting is split into steps
+    /// of at most `interval` and `tick` is called between them, both when waiting for events and
+    /// when polling. The first call happens `interval` after waiting started. If `tick` returns
+    /// `ControlFlow::Break` waiting stops and an error with `ErrorKind::Interrupted` is returned,
+    /// the same as if it was cancelled.
+    ///
+    /// Short intervals make the thread wake up more often so they should only be as short as the
+    /// work done by `tick` needs. A zero `interval` returns an error with
+    /// `ErrorKind::InvalidInput` right away.
+    pub fn open_when_created_with_tick<P: AsRef<Path>, F: FnMut() -> ControlFlow<()>>(&self, path: P, interval: Duration, mut tick: F) -> io::Result<File> {
+        if interval.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the tick interval must not be zero"));
         }
+        let mut context = self.wait_context(None);
+        let now = context.clock.now();
+        context.beats = self.beats(now, Some(Beats::new(now, interval, &mut tick)));
+        let (file, _) = self.wait_with_context(path.as_ref(), &context, &|path, kind| self.attempt_open(path, kind))?;
+        Ok(file)
     }
-}
 
-/// Wait for file being available and open it for reading once it is falling back on some errors.
-///
-/// If `inotify` is unavailable this will poll every 2 seconds.
-///
-/// This is a shorthand for creating `Options`, setting `retry_on_fluke` to `true` and
-/// `polling_fallback_interval` to two seconds then calling `open_when_created`.
-pub fn robust_wait_read<P: AsRef<Path>>(path: P) -> io::Result<File> {
-    let mut open_options = OpenOptions::new();
-    open_options.read(true);
+    /// Opens the file in an already-open directory once it's available.
+    ///
+    /// This behaves the same as `open_when_created` but the file is looked up relative to the
+    /// directory `dir` refers to rather than by traversing a path. Each attempt opens it using
+    /// `openat()` on `dir` so the file is found even if the directory is renamed or the path
+    /// leading to it changes. The watch is added through `/proc/self/fd/<dir>` which resolves
+    /// straight to the directory. If `/proc` is not mounted the watch can not be added so this
+    /// falls back to polling, or fails if polling fallback is disabled, but a file which already
+    /// exists is still opened.
+    ///
+    /// The file is always opened read-only since the access mode and creation flags can not be
+    /// read back from the `OpenOptions` given to the builder. The opener set by `with_opener` is
+    /// not used either. The other options, e.g. `follow_symlinks` or `expect_file_type`, apply.
+    ///
+    /// `name` must be a single path component, otherwise an error with
+    /// `ErrorKind::InvalidInput` is returned.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn open_when_created_at<D: AsRawFd + ?Sized>(&self, dir: &D, name: &OsStr) -> io::Result<File> {
+        self.internal_open_when_created_at(dir.as_raw_fd(), name).map_err(Into::into)
+    }
 
-    Options::with_open_options(open_options)
-        .retry_on_fluke(true)
-        .polling_fallback_interval(Duration::from_secs(2))
-        .open_when_created(path)
-}
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn internal_open_when_created_at(&self, dir: RawFd, name: &OsStr) -> Result<File, WaitError> {
+        use std::os::unix::ffi::OsStrExt;
 
-/// Wait for file being available and open it for reading and writing once it is falling back on some errors.
-///
-/// If `inotify` is unavailable this will poll every 2 seconds.
-///
-/// This is a shorthand for creating `Options`, setting `retry_on_fluke` to `true` and
-/// `polling_fallback_interval` to two seconds then calling `open_when_created`.
-pub fn robust_wait_read_write<P: AsRef<Path>>(path: P) -> io::Result<File> {
-    let mut open_options = OpenOptions::new();
-    open_options.read(true).write(true);
+        check_single_component(name)?;
+        let c_name = std::ffi::CString::new(name.as_bytes())
+            .map_err(|_| WaitError::Open(io::Error::new(io::ErrorKind::InvalidInput, "the name must not contain a null byte")))?;
 
-    Options::with_open_options(open_options)
-        .retry_on_fluke(true)
-        .polling_fallback_interval(Duration::from_secs(2))
-        .open_when_created(path)
-}
+        // Only used for the watch, the attempts ignore it
+        let path = Path::new("/proc/self/fd").join(dir.to_string()).join(name);
+        self.internal_wait(&path, None, &|_, kind| self.attempt_open_at(dir, &c_name, kind)).map(|(file, _)| file)
+    }
 
-/// Wait for file being available and open it for reading and appending once it is falling back on some errors.
-///
-/// If `inotify` is unavailable this will poll every 2 seconds.
-///
-/// This is a shorthand for creating `Options`, setting `retry_on_fluke` to `true` and
-/// `polling_fallback_interval` to two seconds then calling `open_when_created`.
-pub fn robust_wait_read_append<P: AsRef<Path>>(path: P) -> io::Result<File> {
-    let mut open_options = OpenOptions::new();
-    open_options.read(true).append(true);
+    /// Opens the file once it's available using the provided inotify instance.
+    ///
+    /// This behaves the same as `open_when_created` but instead of creating a new inotify
+    /// instance it adds a watch for the parent directory to the given one and removes it
+    /// afterwards.
+    ///
+    /// The instance is used exclusively during the call. All events read from it while waiting,
+    /// including those of other watches, are consumed and discarded. The instance must not
+    /// already watch the parent directory since the watch is removed when this returns.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn open_when_created_with_inotify<P: AsRef<Path>>(&self, inotify: &mut inotify::Inotify, path: P) -> io::Result<File> {
+        self.internal_open_when_created_with_inotify(inotify, path.as_ref()).map_err(Into::into)
+    }
 
-    Options::with_open_options(open_options)
-        .retry_on_fluke(true)
-        .polling_fallback_interval(Duration::from_secs(2))
-        .open_when_created(path)
-}
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn internal_open_when_created_with_inotify(&self, inotify: &mut inotify::Inotify, path: &Path) -> Result<File, WaitError> {
+        let context = self.wait_context(None);
+        let attempt = |path: &Path, kind| self.attempt_open(path, kind);
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn test_wait() {
+        let (dir, file_name) = match self.watch_target(path) {
+            Ok(Some(split)) => split,
+            Ok(None) => return self.try_fallback_open(path, WaitError::AddWatch(io::Error::new(io::ErrorKind::InvalidInput, "the path doesn't contain a file name")), &context, &attempt).map(|(file, _)| file),
+            Err(error) => return Err(WaitError::AddWatch(error)),
+        };
+
+        let mut watcher = backend::DirWatcher::with_inotify(inotify, self);
+        let watch = match watcher.watch(&dir) {
+            Ok(watch) => watch,
+            Err(error) => return self.try_fallback_open(path, WaitError::add_watch(error), &context, &attempt).map(|(file, _)| file),
+        };
+
+        let result = self.wait_for_file(&mut watcher, &mut EventBuffer::new(self), path, file_name, &context, &attempt);
+        // The watch may be already removed by the kernel if the directory was deleted
+        let _ = watcher.unwatch(watch);
+        result.map(|(file, _)| file)
+    }
+
+    /// Opens the file once it's available using a watch the caller already has.
+    ///
+    /// This behaves the same as `open_when_created_with_inotify` but instead of adding a watch
+    /// it relies on `watch` which must be a watch of `dir` in `inotify`. This avoids a duplicate
+    /// watch counting against the limit of watches when the application watches the directory
+    /// anyway. The watch is left in place when this returns.
+    ///
+    /// The mask of the watch must include the events needed to notice the file:
+    ///
+    /// * `CLOSE_WRITE`, `MOVED_TO` and `DELETE_SELF` are always needed
+    /// * `CREATE` if `assume_create_is_atomic` or `expect_directory` is set
+    /// * `MODIFY` if `wait_for_nonempty`, `wait_for_min_size`, `wait_for_prefix` or
+    ///   `wait_for_quiescence` is set
+    /// * `ATTRIB` if `retry_on_permission_denied` is set
+    ///
+    /// Missing events can not be detected so waiting may block forever without them unless
+    /// `inotify_with_polling` is used. Events of other watches read while waiting are consumed and
+    /// discarded as in `open_when_created_with_inotify`.
+    ///
+    /// `name` must be a single path component, otherwise an error with
+    /// `ErrorKind::InvalidInput` is returned.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn open_when_created_in_watched(&self, inotify: &mut inotify::Inotify, watch: &inotify::WatchDescriptor, dir: &Path, name: &OsStr) -> io::Result<File> {
+        self.internal_open_when_created_in_watched(inotify, watch, dir, name).map_err(Into::into)
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn internal_open_when_created_in_watched(&self, inotify: &mut inotify::Inotify, watch: &inotify::WatchDescriptor, dir: &Path, name: &OsStr) -> Result<File, WaitError> {
+        check_single_component(name)?;
+
+        let context = self.wait_context(None);
+        let attempt = |path: &Path, kind| self.attempt_open(path, kind);
+        let path = dir.join(name);
+        let mut watcher = inotify_backend::SingleWatch::new(backend::DirWatcher::with_inotify(inotify, self), watch);
+        self.wait_for_file(&mut watcher, &mut EventBuffer::new(self), &path, name, &context, &attempt).map(|(file, _)| file)
+    }
+
+    /// Attempts to open the file once without waiting.
+    ///
+    /// Returns `Ok(None)` if the file doesn't exist yet or it isn't ready according to the
+    /// options, e.g. it's smaller than required by `wait_for_min_size`. This is a building block
+    /// for callers having their own timers. No watch is set up so to wait without races after
+    /// this returns `None` use `watcher` instead which checks the file after setting up the watch.
+    pub fn try_open_now<P: AsRef<Path>>(&self, path: P) -> io::Result<Option<File>> {
+        match self.attempt_open(path.as_ref(), CreationKind::NoEvent) {
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            result => result,
+        }
+    }
+
+    /// Returns the settings of the waiting logic for driving `WaitState` with custom I/O.
+    pub fn wait_config(&self) -> WaitConfig {
+        WaitConfig {
+            retry_flukes: self.retry_flukes,
+            max_fluke_retries: self.max_fluke_retries,
+            fluke_cooldown: self.fluke_cooldown,
+            polling: self.polling_fallback,
+        }
+    }
+
+    /// Waits until the file exists without opening it.
+    ///
+    /// This uses the same mechanism as `open_when_created` but instead of opening the file it only
+    /// checks whether it exists. The `OpenOptions` given to the builder are not used.
+    ///
+    /// Note that the file may be deleted or replaced right after this function returns so
+    /// whatever uses the path later must be able to handle it. Prefer `open_when_created` if you
+    /// can.
+    #[inline]
+    pub fn wait_only<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.internal_wait(path.as_ref(), None, &|path, _| self.attempt_exists(path)).map(drop).map_err(Into::into)
+    }
+
+    /// Waits until the file exists and returns its metadata without opening it.
+    ///
+    /// This is the same as `wait_only` except it returns the metadata obtained when checking
+    /// that the file exists, e.g. to get its modification time. No file descriptor is held at any
+    /// point which is useful when waiting for a lot of files at once.
+    ///
+    /// The metadata is obtained from the path so there's a small window between the event and the
+    /// check in which the file could be modified, replaced or deleted. In the last case waiting
+    /// continues. Use `open_when_created_with_metadata` if the metadata has to describe the file
+    /// that is actually accessed later.
+    pub fn wait_until_created_stat<P: AsRef<Path>>(&self, path: P) -> io::Result<std::fs::Metadata> {
+        self.internal_wait(path.as_ref(), None, &|path, _| self.attempt_exists(path))
+            .map(|(metadata, _)| metadata)
+            .map_err(Into::into)
+    }
+
+    fn internal_open_when_created(&self, path: &Path, cancellation: Option<&CancellationToken>) -> Result<(File, CreationKind), WaitError> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("wait_file_created", path = %path.display()).entered();
+
+        self.internal_wait(path, cancellation, &|path, kind| self.attempt_open(path, kind))
+    }
+
+    /// Opens the file returning `None` if it's not ready yet.
+    ///
+    /// `kind` is the kind of the last event concerning the file.
+    fn attempt_open(&self, path: &Path, kind: CreationKind) -> io::Result<Option<File>> {
+        if self.only_accept_rename && kind != CreationKind::MovedTo {
+            return Ok(None);
+        }
+        #[cfg(unix)]
+        if self.file_type != FileType::Any && !self.file_type.matches(self.stat(path)?.file_type()) {
+            return Ok(None);
+        }
+
+        self.check_opened(retry_interrupted(|| self.open_path(path)), kind)
+    }
+
+    /// Opens the file `name` in the directory `dir` using `openat()` returning `None` if it's not
+    /// ready yet.
+    ///
+    /// This is the same as `attempt_open` except the path is never resolved.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn attempt_open_at(&self, dir: RawFd, name: &CStr, kind: CreationKind) -> io::Result<Option<File>> {
+        if self.only_accept_rename && kind != CreationKind::MovedTo {
+            return Ok(None);
+        }
+        if self.file_type != FileType::Any && !self.file_type.matches_mode(self.stat_at(dir, name)?) {
+            return Ok(None);
+        }
+
+        self.check_opened(retry_interrupted(|| self.open_at(dir, name)), kind)
+    }
+
+    /// Checks whether the just opened file is ready handling errors of opening it.
+    fn check_opened(&self, result: io::Result<File>, kind: CreationKind) -> io::Result<Option<File>> {
+        let file = match result {
+            Ok(file) => file,
+            // The permissions may still be changed by the producer
+            Err(error) if error.kind() == io::ErrorKind::PermissionDenied && self.retry_permission_denied => return Ok(record_retried(error)),
+            Err(error) if error.kind() != io::ErrorKind::NotFound && self.retry_on_error.as_ref().is_some_and(|retry| (retry.0)(&error)) => return Ok(record_retried(error)),
+            Err(error) => return Err(error),
+        };
+        #[cfg(unix)]
+        if self.file_type != FileType::Any && !self.file_type.matches(file.metadata()?.file_type()) {
+            return Ok(None);
+        }
+        if self.expect_directory {
+            return Ok(Some(file));
+        }
+
+        let min_size = self.min_size.max(self.wait_for_nonempty.into());
+        if min_size > 0 {
+            let len = file.metadata()?.len();
+            // The writer closing a non-empty file means it's most likely done
+            if len < min_size && !(kind == CreationKind::ClosedWrite && (len > 0 || self.allow_empty)) {
+                return Ok(None);
+            }
+        }
+        if let Some(prefix) = &self.prefix {
+            use std::io::{Read, Seek};
+
+            let mut start = Vec::with_capacity(prefix.len());
+            (&file).take(prefix.len() as u64).read_to_end(&mut start)?;
+            (&file).seek(io::SeekFrom::Start(0))?;
+            if *start != **prefix {
+                return Ok(None);
+            }
+        }
+        Ok(Some(file))
+    }
+
+    /// Returns the metadata of the file following symlinks only if `follow_symlinks` is set.
+    #[cfg(unix)]
+    fn stat(&self, path: &Path) -> io::Result<std::fs::Metadata> {
+        if self.follow_symlinks {
+            std::fs::metadata(path)
+        } else {
+            std::fs::symlink_metadata(path)
+        }
+    }
+
+    /// Opens the file or the directory honoring `expect_directory` and `follow_symlinks`.
+    #[cfg(unix)]
+    fn open_path(&self, path: &Path) -> io::Result<File> {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let nofollow = if self.follow_symlinks { 0 } else { libc::O_NOFOLLOW };
+        // Opening a FIFO blocks until the other end is opened
+        let nonblock = if self.file_type == FileType::Fifo { libc::O_NONBLOCK } else { 0 };
+        if self.expect_directory {
+            OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_DIRECTORY | nofollow)
+                .open(path)
+        } else if let Some(opener) = &self.opener {
+            (opener.0)(path)
+        } else if nofollow | nonblock == 0 {
+            self.open_options.open(path)
+        } else {
+            self.open_options.clone().custom_flags(nofollow | nonblock).open(path)
+        }
+    }
+
+    /// Returns the mode of the file `name` in `dir` following symlinks only if `follow_symlinks`
+    /// is set.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn stat_at(&self, dir: RawFd, name: &CStr) -> io::Result<libc::mode_t> {
+        let flags = if self.follow_symlinks { 0 } else { libc::AT_SYMLINK_NOFOLLOW };
+        let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
+        // SAFETY: the name is a valid C string and the pointer points to memory for a single stat
+        if unsafe { libc::fstatat(dir, name.as_ptr(), stat.as_mut_ptr(), flags) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: fstatat succeeded so it initialized the stat
+        Ok(unsafe { stat.assume_init() }.st_mode)
+    }
+
+    /// Opens the file or the directory `name` in `dir` for reading honoring `expect_directory`
+    /// and `follow_symlinks`.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn open_at(&self, dir: RawFd, name: &CStr) -> io::Result<File> {
+        let nofollow = if self.follow_symlinks { 0 } else { libc::O_NOFOLLOW };
+        // Opening a FIFO blocks until the other end is opened
+        let nonblock = if self.file_type == FileType::Fifo { libc::O_NONBLOCK } else { 0 };
+        let directory = if self.expect_directory { libc::O_DIRECTORY } else { 0 };
+        let flags = libc::O_RDONLY | libc::O_CLOEXEC | libc::O_NOCTTY | nofollow | nonblock | directory;
+        // SAFETY: the name is a valid C string
+        let fd = unsafe { libc::openat(dir, name.as_ptr(), flags) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the fd was just opened and nothing else owns it
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+
+    /// Opens the file or the directory honoring `expect_directory` and `follow_symlinks`.
+    #[cfg(windows)]
+    fn open_path(&self, path: &Path) -> io::Result<File> {
+        use std::os::windows::fs::OpenOptionsExt;
+
+        let nofollow = if self.follow_symlinks { 0 } else { backend::FILE_FLAG_OPEN_REPARSE_POINT };
+        let file = if self.expect_directory {
+            OpenOptions::new()
+                .read(true)
+                .custom_flags(backend::FILE_FLAG_OPEN_DIRECTORY | nofollow)
+                .open(path)?
+        } else if let Some(opener) = &self.opener {
+            (opener.0)(path)?
+        } else if self.follow_symlinks {
+            self.open_options.open(path)?
+        } else {
+            self.open_options.clone().custom_flags(nofollow).open(path)?
+        };
+
+        // Windows opens symlinks and files instead of failing like Unix does
+        if !self.follow_symlinks && file.metadata()?.file_type().is_symlink() {
+            return Err(symlink_error());
+        }
+        if self.expect_directory && !file.metadata()?.is_dir() {
+            return Err(not_directory_error());
+        }
+        Ok(file)
+    }
+
+    /// Checks that the file exists returning its metadata.
+    fn attempt_exists(&self, path: &Path) -> io::Result<Option<std::fs::Metadata>> {
+        let metadata = if self.follow_symlinks {
+            std::fs::metadata(path)?
+        } else {
+            let metadata = std::fs::symlink_metadata(path)?;
+            if metadata.file_type().is_symlink() {
+                return Err(symlink_error());
+            }
+            metadata
+        };
+        if self.expect_directory && !metadata.is_dir() {
+            return Err(not_directory_error());
+        }
+        #[cfg(unix)]
+        if !self.file_type.matches(metadata.file_type()) {
+            return Ok(None);
+        }
+        Ok(Some(metadata))
+    }
+
+    /// Waits until `attempt` succeeds.
+    ///
+    /// Failing with `NotFound` or returning `None` means that the file is not ready yet.
+    fn internal_wait<T>(&self, path: &Path, cancellation: Option<&CancellationToken>, attempt: &Attempt<'_, T>) -> Result<(T, CreationKind), WaitError> {
+        let context = self.wait_context(cancellation);
+        self.wait_with_context(path, &context, attempt)
+    }
+
+    /// Returns the deadline of a wait starting at `now` honoring both `timeout` and `deadline`.
+    fn deadline_from(&self, now: Instant) -> Option<Instant> {
+        match (self.timeout.map(|timeout| now + timeout), self.deadline) {
+            (Some(timeout), Some(deadline)) => Some(timeout.min(deadline)),
+            (timeout, deadline) => timeout.or(deadline),
+        }
+    }
+
+    /// Returns how long a wait starting now may take if it's limited.
+    #[cfg(any(feature = "tokio", feature = "async-io"))]
+    fn time_limit(&self) -> Option<Duration> {
+        let now = self.clock().now();
+        self.deadline_from(now).map(|deadline| deadline.saturating_duration_since(now))
+    }
+
+    fn clock(&self) -> &dyn Clock {
+        self.clock.as_deref().unwrap_or(&SystemClock)
+    }
+
+    /// Creates the context of a wait operation starting now.
+    fn wait_context<'a>(&'a self, cancellation: Option<&'a CancellationToken>) -> WaitContext<'a> {
+        let clock = self.clock();
+        WaitContext {
+            deadline: self.deadline_from(clock.now()),
+            cancellation,
+            clock,
+            beats: self.beats(clock.now(), None),
+            mechanism: Cell::new(Mechanism::Inotify),
+            progress: Cell::default(),
+        }
+    }
+
+    /// Schedules the heartbeats and the `tick` of a wait starting at `now`.
+    fn beats<'a>(&'a self, now: Instant, tick: Option<Beats<'a>>) -> Rc<[Beats<'a>]> {
+        let heartbeat = self.heartbeat.as_ref().map(|heartbeat| Beats::new(now, heartbeat.interval, move || {
+            heartbeat.call();
+            ControlFlow::Continue(())
+        }));
+        heartbeat.into_iter().chain(tick).collect()
+    }
+
+    fn wait_with_context<T>(&self, path: &Path, context: &WaitContext<'_>, attempt: &Attempt<'_, T>) -> Result<(T, CreationKind), WaitError> {
+        match self.wait_file(path, context, attempt) {
+            // The events creating the file may not be watched so this tells whether the producer
+            // started at all
+            Err(WaitError::TimedOut { saw_close_write, .. }) if std::fs::symlink_metadata(path).is_ok() => {
+                Err(WaitError::TimedOut { saw_create: true, saw_close_write })
+            },
+            result => result,
+        }
+    }
+
+    fn wait_file<T>(&self, path: &Path, context: &WaitContext<'_>, attempt: &Attempt<'_, T>) -> Result<(T, CreationKind), WaitError> {
+        check_name_len(path)?;
+
+        if self.polling_only {
+            return self.try_fallback_open(path, WaitError::InotifyInit(inotify_disabled()), context, attempt);
+        }
+
+        // Fast path avoiding inotify syscalls if the file is already there.
+        // It's attempted again once the watch is set up so there's no race.
+        // Waiting for quiescence needs the watch anyway.
+        if self.quiescence.is_none() {
+            match attempt(path, CreationKind::NoEvent) {
+                Ok(Some(value)) => return Ok((value, CreationKind::NoEvent)),
+                Ok(None) => (),
+                Err(error) if error.kind() == io::ErrorKind::NotFound => (),
+                Err(error) => return Err(WaitError::Open(error)),
+            }
+        }
+
+        let (dir, file_name) = match self.watch_target(path) {
+            Ok(Some(split)) => split,
+            Ok(None) => return self.try_fallback_open(path, WaitError::AddWatch(io::Error::new(io::ErrorKind::InvalidInput, "the path doesn't contain a file name")), context, attempt),
+            Err(error) => return Err(WaitError::AddWatch(error)),
+        };
+
+        let watcher = backend::DirWatcher::new(self);
+        trace_event!(ok = watcher.is_ok(), "inotify_init");
+        match watcher {
+            Ok(mut watcher) => {
+                loop {
+                    match watcher.watch(&dir) {
+                        Ok(_) => {
+                            trace_event!(dir = %dir.display(), "watch_added");
+                            break;
+                        },
+                        Err(error) if error.kind() == io::ErrorKind::NotFound && self.wait_for_parent => self.wait_parent(&dir, context)?,
+                        Err(error) => return self.try_fallback_open(path, WaitError::add_watch(error), context, attempt),
+                    }
+                }
+
+                self.wait_for_file(&mut watcher, &mut EventBuffer::new(self), path, file_name, context, attempt)
+
+            },
+            Err(error) => self.try_fallback_open(path, WaitError::inotify_init(error), context, attempt),
+        }
+    }
+
+    /// Returns the directory to watch and the name of the file in it.
+    ///
+    /// Returns `None` if the path doesn't contain a file name and an error if the directory
+    /// couldn't be canonicalized.
+    fn watch_target<'p>(&'p self, path: &'p Path) -> io::Result<Option<(Cow<'p, Path>, &'p OsStr)>> {
+        let (dir, file_name) = match split_path(path) {
+            Some(split) => split,
+            None => return Ok(None),
+        };
+        let dir = self.watch_dir.as_deref().unwrap_or(dir);
+        let dir = if self.canonicalize_parent {
+            Cow::Owned(dir.canonicalize()?)
+        } else {
+            Cow::Borrowed(dir)
+        };
+        Ok(Some((dir, file_name)))
+    }
+
+    /// Returns `true` if modifications of the file have to be watched because its contents are
+    /// checked.
+    #[cfg_attr(any(target_os = "macos", target_os = "freebsd", target_os = "netbsd", target_os = "openbsd", target_os = "dragonfly"), allow(dead_code))]
+    fn watches_modifications(&self) -> bool {
+        self.wait_for_nonempty || self.min_size > 0 || self.prefix.is_some() || self.quiescence.is_some()
+    }
+
+    /// Returns `true` if the event means the entry can be opened.
+    fn is_created(&self, kind: CreationKind) -> bool {
+        // Without close notifications creation is the only event telling the file is there
+        let atomic = self.create_is_atomic || self.expect_directory || !backend::REPORTS_CLOSE_WRITE;
+        match kind {
+            CreationKind::MovedTo => true,
+            CreationKind::Created => atomic,
+            CreationKind::ClosedWrite => !atomic,
+            _ => false,
+        }
+    }
+
+    /// Waits for the missing parent directory to be created.
+    ///
+    /// Its own missing parents are waited for recursively.
+    fn wait_parent(&self, dir: &Path, context: &WaitContext<'_>) -> Result<(), WaitError> {
+        let mut options = self.clone()
+            .expect_directory(true)
+            .wait_for_nonempty(false)
+            .wait_for_min_size(0)
+            .follow_symlinks(true);
+        options.prefix = None;
+        options.quiescence = None;
+        options.wait_with_context(dir, context, &|path, _| options.attempt_exists(path)).map(drop)
+    }
+
+    fn try_fallback_open<T>(&self, path: &Path, inotify_error: WaitError, context: &WaitContext<'_>, attempt: &Attempt<'_, T>) -> Result<(T, CreationKind), WaitError> {
+        if self.only_accept_rename {
+            return Err(inotify_error);
+        }
+        let mut state = WaitState::polling(self.wait_config());
+        let mut last = None;
+        // Unlike `last` this isn't cleared by the following attempts
+        let mut last_failure = None;
+        take_retried();
+        let mut action = state.start();
+        loop {
+            action = match action {
+                WaitAction::Open(kind) => {
+                    context.check_cancelled()?;
+                    trace_event!(path = %path.display(), error = %inotify_error, "fallback_poll");
+                    let result = open_result(attempt(path, kind), &mut last);
+                    if let Some(error) = take_retried() {
+                        last_failure = Some(error);
+                    }
+                    context.observed_open(result);
+                    state.open_result(result)
+                },
+                WaitAction::Sleep(delay) => {
+                    match context.sleep(delay) {
+                        Ok(()) => (),
+                        Err(WaitError::TimedOut { .. }) => {
+                            let last_open = match (last_failure, last) {
+                                (Some(error), _) | (None, Some(Err(error))) => Some(error),
+                                _ => None,
+                            };
+                            return Err(WaitError::PollingTimedOut { watch: Box::new(inotify_error), last_open });
+                        },
+                        Err(error) => return Err(error),
+                    }
+                    state.slept()
+                },
+                WaitAction::Wait => unreachable!("polling never waits for events"),
+                WaitAction::Return(WaitOutcome::Opened(kind)) => {
+                    self.wait_unchanged(path, context)?;
+                    trace_event!(kind = ?kind, "opened");
+                    context.mechanism.set(Mechanism::Polling);
+                    return Ok((take_opened(&mut last), kind));
+                },
+                WaitAction::Return(WaitOutcome::Unavailable) => return Err(inotify_error),
+                WaitAction::Return(outcome) => return Err(take_error(&mut last, outcome)),
+            }
+        }
+    }
+
+    /// Waits for the file using events from the watcher.
+    ///
+    /// The buffer is passed in so that long-lived waiters can reuse it.
+    fn wait_for_file<T, W: ChangeSource>(&self, watcher: &mut W, buffer: &mut [u8], path: &Path, file_name: &OsStr, context: &WaitContext<'_>, attempt: &Attempt<'_, T>) -> Result<(T, CreationKind), WaitError> {
+        let mut state = WaitState::watching(self.wait_config());
+        let mut last = None;
+        let mut action = state.start();
+        loop {
+            action = match action {
+                WaitAction::Open(kind) => {
+                    let result = open_result(attempt(path, kind), &mut last);
+                    context.observed_open(result);
+                    state.open_result(result)
+                },
+                WaitAction::Wait => {
+                    #[cfg(all(test, test_delay_after_check))]
+                    {
+                        // We want to make sure we DO receive notification after we checked existence of
+                        // the file even though we didn't ACTIVELY wait for it yet.
+                        std::thread::sleep(std::time::Duration::from_secs(7));
+                    }
+
+                    let mut spurious_wakeups = 0;
+                    let readiness = loop {
+                        match context.limited(self.recheck_interval).wait_readable(&*watcher) {
+                            Ok(Wakeup::Readable) => (),
+                            // Time to re-check in case events were lost
+                            Ok(Wakeup::TimedOut) if !context.is_expired() => break None,
+                            Ok(Wakeup::TimedOut) => return Err(context.timed_out()),
+                            Ok(Wakeup::Cancelled) => return Err(WaitError::Cancelled),
+                            Err(error) => {
+                                context.lost_watch();
+                                return self.try_fallback_open(path, WaitError::ReadEvents(error), context, attempt);
+                            },
+                        }
+
+                        match scan_changes(watcher, buffer, file_name) {
+                            Ok(Some(readiness)) => break Some(readiness),
+                            Ok(None) => spurious_wakeups = 0,
+                            // Readable but nothing was read. If it keeps happening waiting for
+                            // readability returns immediately so sleep instead to avoid spinning.
+                            Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                                spurious_wakeups += 1;
+                                if spurious_wakeups > 1 {
+                                    context.sleep(SPURIOUS_WAKEUP_DELAY)?;
+                                }
+                            },
+                            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                                context.lost_watch();
+                                return self.parent_removed(path, error, context, attempt);
+                            },
+                            Err(error) => {
+                                context.lost_watch();
+                                return self.try_fallback_open(path, WaitError::ReadEvents(error), context, attempt);
+                            },
+                        }
+                    };
+
+                    let notification = match readiness {
+                        Some(Readiness::Available(kind)) => {
+                            context.observed(kind);
+                            Notification::Available(kind)
+                        },
+                        Some(Readiness::Maybe) => Notification::Maybe,
+                        None => Notification::Recheck,
+                    };
+                    trace_event!(notification = ?notification, "event_received");
+                    state.notify(notification)
+                },
+                WaitAction::Sleep(cooldown) => {
+                    context.sleep(cooldown)?;
+                    state.slept()
+                },
+                WaitAction::Return(WaitOutcome::Opened(kind)) => {
+                    let value = take_opened(&mut last);
+                    if let Some(quiet) = self.quiescence {
+                        self.wait_quiescent(watcher, buffer, path, file_name, quiet, context)?;
+                    }
+                    trace_event!(kind = ?kind, "opened");
+                    context.mechanism.set(Mechanism::Inotify);
+                    return Ok((value, kind));
+                },
+                WaitAction::Return(outcome) => return Err(take_error(&mut last, outcome)),
+            }
+        }
+    }
+
+    /// Continues waiting after the watched parent directory was removed according to the policy.
+    fn parent_removed<T>(&self, path: &Path, error: io::Error, context: &WaitContext<'_>, attempt: &Attempt<'_, T>) -> Result<(T, CreationKind), WaitError> {
+        match self.parent_removed {
+            ParentRemovedPolicy::Error => Err(WaitError::ParentRemoved),
+            ParentRemovedPolicy::RecreateWatch => {
+                let mut options = self.clone();
+                options.wait_for_parent = true;
+                // The nested wait counts the remaining attempts
+                match options.max_watch_recreations {
+                    Some(0) => return Err(WaitError::ParentRemoved),
+                    Some(max) => options.max_watch_recreations = Some(max - 1),
+                    None => (),
+                }
+                options.wait_with_context(path, context, attempt)
+            },
+            ParentRemovedPolicy::FallBackToPolling => self.try_fallback_open(path, WaitError::ReadEvents(error), context, attempt),
+        }
+    }
+
+    /// Waits until the file is not modified for `quiet` using events from the watcher.
+    fn wait_quiescent<W: ChangeSource>(&self, watcher: &mut W, buffer: &mut [u8], path: &Path, file_name: &OsStr, quiet: Duration, context: &WaitContext<'_>) -> Result<(), WaitError> {
+        let mut quiet_deadline = context.clock.now() + quiet;
+        loop {
+            let quiet_context = WaitContext {
+                deadline: Some(context.deadline.map_or(quiet_deadline, |deadline| deadline.min(quiet_deadline))),
+                cancellation: context.cancellation,
+                clock: context.clock,
+                beats: context.beats.clone(),
+                mechanism: Cell::new(Mechanism::Inotify),
+                progress: Cell::default(),
+            };
+
+            match quiet_context.wait_readable(&*watcher) {
+                Ok(Wakeup::Readable) => (),
+                Ok(Wakeup::TimedOut) if context.deadline.is_some_and(|deadline| deadline < quiet_deadline) => return Err(context.timed_out()),
+                Ok(Wakeup::TimedOut) => return Ok(()),
+                Ok(Wakeup::Cancelled) => return Err(WaitError::Cancelled),
+                Err(_) => return self.wait_unchanged(path, context),
+            }
+
+            let mut modified = false;
+            let result = watcher.read_changes(buffer, |_, change| match change {
+                Change::Entry(name, _) if name == file_name => modified = true,
+                Change::Entry(_, _) | Change::Removed => (),
+                // We don't know if the change was related to our file
+                Change::Unknown | Change::Overflow => modified = true,
+            });
+
+            match result {
+                Ok(()) => (),
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => (),
+                Err(_) => return self.wait_unchanged(path, context),
+            }
+
+            if modified {
+                quiet_deadline = context.clock.now() + quiet;
+            }
+        }
+    }
+
+    /// Waits until the size and modification time of the file don't change for the quiescence
+    /// period.
+    ///
+    /// Does nothing if waiting for quiescence wasn't requested.
+    fn wait_unchanged(&self, path: &Path, context: &WaitContext<'_>) -> Result<(), WaitError> {
+        let quiet = match self.quiescence {
+            Some(quiet) => quiet,
+            None => return Ok(()),
+        };
+
+        let mut last = file_state(path).map_err(WaitError::Open)?;
+        let mut since = context.clock.now();
+        loop {
+            let elapsed = context.clock.now().saturating_duration_since(since);
+            if elapsed >= quiet {
+                return Ok(());
+            }
+            context.sleep(quiet - elapsed)?;
+            context.check_cancelled()?;
+
+            let current = file_state(path).map_err(WaitError::Open)?;
+            if current != last {
+                last = current;
+                since = context.clock.now();
+            }
+        }
+    }
+}
+
+/// Checks whether the file is ready and returns it if it is.
+///
+/// `Ok(None)` or an error with `ErrorKind::NotFound` mean the file isn't ready yet.
+///
+/// The second argument is the kind of the last event concerning the file.
+type Attempt<'a, T> = dyn Fn(&Path, CreationKind) -> io::Result<Option<T>> + 'a;
+
+/// Kind of the event after which the file was opened.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum CreationKind {
+    /// The file was opened without receiving any event.
+    ///
+    /// This happens when the file already existed or it was found by the polling fallback.
+    NoEvent,
+    /// The file was closed after it was opened for writing.
+    ClosedWrite,
+    /// The file was moved (renamed) to the expected path.
+    MovedTo,
+    /// The file was created.
+    ///
+    /// This can only happen if `assume_create_is_atomic` was set to `true` or on Windows which
+    /// doesn't report closing files.
+    Created,
+    /// The file was modified.
+    ///
+    /// This can only happen if `wait_for_nonempty` or `wait_for_min_size` was set.
+    Modified,
+    /// The attributes of the file, such as permissions, changed.
+    ///
+    /// This can only happen if `retry_on_permission_denied` was set to `true`.
+    AttributesChanged,
+    /// The directory containing the file was modified.
+    ///
+    /// This is reported on platforms which can not tell which entry of the directory changed
+    /// (kqueue-based ones).
+    DirectoryChanged,
+    /// The file was deleted or moved away.
+    ///
+    /// The file is never opened after this, it's only returned by `Watcher::wait_event` on
+    /// platforms using inotify.
+    Deleted,
+}
+
+/// Change in a watched directory as reported by the platform backend.
+pub(crate) enum Change<'a> {
+    /// An entry with the given name became available.
+    #[cfg_attr(any(target_os = "macos", target_os = "freebsd", target_os = "netbsd", target_os = "openbsd", target_os = "dragonfly"), allow(dead_code))]
+    Entry(&'a OsStr, CreationKind),
+    /// The directory was modified but it's unknown which entry changed.
+    #[cfg_attr(not(any(target_os = "macos", target_os = "freebsd", target_os = "netbsd", target_os = "openbsd", target_os = "dragonfly")), allow(dead_code))]
+    Unknown,
+    /// The watch was removed, usually because the directory was deleted.
+    Removed,
+    /// Events were lost so any entry in any watched directory might have changed.
+    #[cfg_attr(any(target_os = "macos", target_os = "freebsd", target_os = "netbsd", target_os = "openbsd", target_os = "dragonfly"), allow(dead_code))]
+    Overflow,
+}
+
+/// Source of changes in watched directories implemented by the platform backends.
+///
+/// The file descriptor becomes readable when there are changes to read.
+#[cfg(unix)]
+pub(crate) trait ChangeSource: AsRawFd {
+    /// Reads the available changes without blocking.
+    ///
+    /// Returns `ErrorKind::WouldBlock` if there are no changes.
+    fn read_changes<F: FnMut(&backend::WatchId, Change<'_>)>(&mut self, buffer: &mut [u8], f: F) -> io::Result<()>;
+}
+
+/// Source of changes in watched directories implemented by the platform backends.
+///
+/// The event handle becomes signaled when there are changes to read.
+#[cfg(windows)]
+pub(crate) trait ChangeSource: AsRawHandle {
+    /// Reads the available changes without blocking.
+    ///
+    /// Returns `ErrorKind::WouldBlock` if there are no changes.
+    fn read_changes<F: FnMut(&backend::WatchId, Change<'_>)>(&mut self, buffer: &mut [u8], f: F) -> io::Result<()>;
+}
+
+/// Type of the file expected by `Options::expect_file_type`.
+#[cfg(unix)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum FileType {
+    /// A regular file.
+    Regular,
+    /// A named pipe.
+    Fifo,
+    /// A unix domain socket.
+    Socket,
+    /// Any type of file.
+    ///
+    /// This is the default.
+    Any,
+}
+
+#[cfg(unix)]
+impl FileType {
+    fn matches(self, file_type: std::fs::FileType) -> bool {
+        use std::os::unix::fs::FileTypeExt;
+
+        match self {
+            FileType::Regular => file_type.is_file(),
+            FileType::Fifo => file_type.is_fifo(),
+            FileType::Socket => file_type.is_socket(),
+            FileType::Any => true,
+        }
+    }
+
+    /// Same as `matches` but takes the mode returned by `stat()`.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn matches_mode(self, mode: libc::mode_t) -> bool {
+        match self {
+            FileType::Regular => mode & libc::S_IFMT == libc::S_IFREG,
+            FileType::Fifo => mode & libc::S_IFMT == libc::S_IFIFO,
+            FileType::Socket => mode & libc::S_IFMT == libc::S_IFSOCK,
+            FileType::Any => true,
+        }
+    }
+}
+
+/// Mechanism which found the file.
+///
+/// See `Options::open_when_created_with_mechanism`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Mechanism {
+    /// The file already existed or it was found using the watch.
+    ///
+    /// On platforms not using inotify this refers to the analogous platform API.
+    Inotify,
+    /// The file was found by the polling fallback.
+    Polling,
+}
+
+/// What to do when the parent directory is removed while waiting.
+///
+/// See `Options::on_parent_removed`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum ParentRemovedPolicy {
+    /// Fail with `WaitError::ParentRemoved`.
+    Error,
+    /// Wait for the directory to be created again and watch it.
+    ///
+    /// Missing ancestors are waited for as if `wait_for_parent` was set.
+    RecreateWatch,
+    /// Continue using the polling fallback or fail with `WaitError::ReadEvents` if it's disabled.
+    ///
+    /// This is the default.
+    FallBackToPolling,
+}
+
+/// What the received changes tell about the file being waited for.
+#[derive(Copy, Clone)]
+enum Readiness {
+    /// The file was reported to be available.
+    Available(CreationKind),
+    /// The directory was modified so the file might be available.
+    Maybe,
+}
+
+/// Allows aborting waiting from another thread.
+///
+/// The token can be cloned and the clones share the same state - cancelling any of them cancels
+/// all waits using any of the clones. Once cancelled the token stays cancelled forever.
+///
+/// Cancellation is immediate even if the waiting thread is blocked waiting for filesystem events.
+#[derive(Clone, Debug)]
+pub struct CancellationToken {
+    inner: Arc<CancellationInner>,
+}
+
+#[derive(Debug)]
+struct CancellationInner {
+    cancelled: AtomicBool,
+    // Self-pipe used to wake up the threads blocked in `poll()`
+    #[cfg(unix)]
+    pipe_read: File,
+    #[cfg(unix)]
+    pipe_write: File,
+    // Manual-reset event used to wake up the threads blocked in `WaitForMultipleObjects()`
+    #[cfg(windows)]
+    event: OwnedHandle,
+}
+
+impl CancellationToken {
+    /// Creates a new, not yet cancelled token.
+    ///
+    /// This fails if the underlying pipe (event on Windows) can not be created.
+    #[cfg(unix)]
+    pub fn new() -> io::Result<Self> {
+        let mut fds = [-1; 2];
+        // SAFETY: the pointer points to an array of two fds
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the fds were just created and nothing else owns them
+        let (pipe_read, pipe_write) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+        for fd in &fds {
+            set_cloexec(*fd)?;
+            set_nonblocking(*fd)?;
+        }
+
+        let inner = CancellationInner {
+            cancelled: AtomicBool::new(false),
+            pipe_read,
+            pipe_write,
+        };
+
+        Ok(CancellationToken { inner: Arc::new(inner), })
+    }
+
+    /// Creates a new, not yet cancelled token.
+    ///
+    /// This fails if the underlying pipe (event on Windows) can not be created.
+    #[cfg(windows)]
+    pub fn new() -> io::Result<Self> {
+        let inner = CancellationInner {
+            cancelled: AtomicBool::new(false),
+            event: backend::create_event()?,
+        };
+
+        Ok(CancellationToken { inner: Arc::new(inner), })
+    }
+
+    /// Cancels all waits using this token.
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::Release);
+        self.wake();
+    }
+
+    #[cfg(unix)]
+    fn wake(&self) {
+        use std::io::Write;
+
+        // The only possible error is full pipe which means it's readable anyway.
+        let _ = (&self.inner.pipe_write).write(&[1]);
+    }
+
+    #[cfg(windows)]
+    fn wake(&self) {
+        // Setting a valid event can not fail
+        let _ = backend::set_event(&self.inner.event);
+    }
+
+    /// Returns `true` if `cancel()` was called on this token or any of its clones.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Sleeps for `duration` waking up early if the token is cancelled.
+    ///
+    /// Returns `true` if the token was cancelled.
+    #[cfg(unix)]
+    fn sleep(&self, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+        let mut pollfd = libc::pollfd {
+            fd: self.inner.pipe_read.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        while !self.is_cancelled() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_secs(0) {
+                return false;
+            }
+            // Round up to avoid spinning when less than a millisecond remains
+            let millis = remaining.as_nanos().div_ceil(1_000_000).min(libc::c_int::MAX as u128) as libc::c_int;
+            // SAFETY: the pointer points to a single valid pollfd
+            if unsafe { libc::poll(&mut pollfd, 1, millis) } == -1 && io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+                // poll() on a valid pipe doesn't fail in practice so just sleeping is good enough
+                std::thread::sleep(remaining);
+            }
+        }
+        true
+    }
+
+    /// Sleeps for `duration` waking up early if the token is cancelled.
+    ///
+    /// Returns `true` if the token was cancelled.
+    #[cfg(windows)]
+    fn sleep(&self, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+        while !self.is_cancelled() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_secs(0) {
+                return false;
+            }
+            // Round up to avoid spinning when less than a millisecond remains
+            let millis = remaining.as_nanos().div_ceil(1_000_000).min(u32::MAX.into()) as u32;
+            if backend::wait_any(&[self.inner.event.as_raw_handle()], Some(millis)).is_err() {
+                // Waiting for a valid event doesn't fail in practice so just sleeping is good enough
+                std::thread::sleep(remaining);
+            }
+        }
+        true
+    }
+}
+
+type OpenerFn = dyn Fn(&Path) -> io::Result<File> + Send + Sync;
+
+/// Custom way of opening files set by `Options::with_opener`.
+#[derive(Clone)]
+struct Opener(Arc<OpenerFn>);
+
+impl fmt::Debug for Opener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Opener")
+    }
+}
+
+/// Periodic callback set by `Options::heartbeat`.
+#[derive(Clone)]
+struct Heartbeat {
+    interval: Duration,
+    callback: Arc<Mutex<dyn FnMut() + Send>>,
+}
+
+impl Heartbeat {
+    fn call(&self) {
+        // The callback can not break our invariants so a panic in previous call is irrelevant
+        let mut callback = self.callback.lock().unwrap_or_else(PoisonError::into_inner);
+        callback()
+    }
+}
+
+impl fmt::Debug for Heartbeat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Heartbeat").field("interval", &self.interval).finish_non_exhaustive()
+    }
+}
+
+/// Periodic callback during a single wait and the time of its next call.
+struct Beats<'a> {
+    interval: Duration,
+    callback: RefCell<Box<dyn FnMut() -> ControlFlow<()> + 'a>>,
+    next: Cell<Instant>,
+    // The callback asked to stop waiting
+    stopped: Cell<bool>,
+}
+
+impl<'a> Beats<'a> {
+    fn new<F: FnMut() -> ControlFlow<()> + 'a>(now: Instant, interval: Duration, callback: F) -> Self {
+        Beats {
+            interval,
+            callback: RefCell::new(Box::new(callback)),
+            next: Cell::new(now + interval),
+            stopped: Cell::new(false),
+        }
+    }
+}
+
+/// Decides whether to retry opening the file set by `Options::retry_on_error`.
+#[derive(Clone)]
+struct ErrorFilter(Arc<dyn Fn(&io::Error) -> bool + Send + Sync>);
+
+impl fmt::Debug for ErrorFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ErrorFilter")
+    }
+}
+
+/// Size of `struct inotify_event` followed by the longest possible name.
+const MIN_EVENT_BUFFER_SIZE: usize = 16 + 255 + 1;
+
+/// Buffer for reading events configured by `Options::event_buffer_size`.
+// Avoiding the allocation by default is the point
+#[allow(clippy::large_enum_variant)]
+enum EventBuffer {
+    Stack([u8; 4096]),
+    Heap(Box<[u8]>),
+}
+
+impl EventBuffer {
+    fn new(options: &Options) -> Self {
+        match options.event_buffer_size {
+            Some(size) => EventBuffer::Heap(vec![0; size].into_boxed_slice()),
+            None => EventBuffer::Stack([0; 4096]),
+        }
+    }
+}
+
+impl std::ops::Deref for EventBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            EventBuffer::Stack(buffer) => buffer,
+            EventBuffer::Heap(buffer) => buffer,
+        }
+    }
+}
+
+impl std::ops::DerefMut for EventBuffer {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            EventBuffer::Stack(buffer) => buffer,
+            EventBuffer::Heap(buffer) => buffer,
+        }
+    }
+}
+
+/// State of single wait operation shared between the inotify and polling paths.
+struct WaitContext<'a> {
+    deadline: Option<Instant>,
+    cancellation: Option<&'a CancellationToken>,
+    clock: &'a dyn Clock,
+    // Shared with the limited contexts so that the schedule is kept
+    beats: Rc<[Beats<'a>]>,
+    // Set by the path which opened the file
+    mechanism: Cell<Mechanism>,
+    // Reported in `WaitError::TimedOut`
+    progress: Cell<Progress>,
+}
+
+/// What was observed about the file while waiting.
+#[derive(Copy, Clone, Default)]
+struct Progress {
+    saw_create: bool,
+    saw_close_write: bool,
+    // An attempt to open the file after setting up the watch didn't succeed
+    missed_open: bool,
+    // The watch given to `wait_for_file` stopped working and was abandoned
+    watch_lost: bool,
+}
+
+enum Wakeup {
+    Readable,
+    TimedOut,
+    Cancelled,
+}
+
+impl<'a> WaitContext<'a> {
+    /// Returns the context with the deadline shortened to `interval` from now if it's given.
+    fn limited(&self, interval: Option<Duration>) -> WaitContext<'a> {
+        let deadline = match (self.deadline, interval.map(|interval| self.clock.now() + interval)) {
+            (Some(deadline), Some(limit)) => Some(deadline.min(limit)),
+            (deadline, limit) => deadline.or(limit),
+        };
+
+        WaitContext {
+            deadline,
+            cancellation: self.cancellation,
+            clock: self.clock,
+            beats: self.beats.clone(),
+            mechanism: Cell::new(Mechanism::Inotify),
+            progress: Cell::default(),
+        }
+    }
+
+    /// Records that the file was reported to change.
+    fn observed(&self, kind: CreationKind) {
+        let mut progress = self.progress.get();
+        match kind {
+            CreationKind::Created | CreationKind::MovedTo | CreationKind::Modified | CreationKind::AttributesChanged => progress.saw_create = true,
+            CreationKind::ClosedWrite => {
+                progress.saw_create = true;
+                progress.saw_close_write = true;
+            },
+            _ => (),
+        }
+        self.progress.set(progress);
+    }
+
+    /// Records the result of an attempt to open the file.
+    fn observed_open(&self, result: OpenResult) {
+        let mut progress = self.progress.get();
+        match result {
+            OpenResult::Opened => progress.saw_create = true,
+            OpenResult::NotReady => {
+                progress.saw_create = true;
+                progress.missed_open = true;
+            },
+            OpenResult::NotFound | OpenResult::Failed => progress.missed_open = true,
+        }
+        self.progress.set(progress);
+    }
+
+    /// Records that the watch can't be used anymore, e.g. because the directory was removed.
+    fn lost_watch(&self) {
+        let mut progress = self.progress.get();
+        progress.watch_lost = true;
+        self.progress.set(progress);
+    }
+
+    /// Returns the error reporting the timeout along with what was observed.
+    fn timed_out(&self) -> WaitError {
+        let progress = self.progress.get();
+        WaitError::TimedOut {
+            saw_create: progress.saw_create,
+            saw_close_write: progress.saw_close_write,
+        }
+    }
+
+    fn is_expired(&self) -> bool {
+        self.deadline.is_some_and(|deadline| self.clock.now() >= deadline)
+    }
+
+    fn check_cancelled(&self) -> Result<(), WaitError> {
+        match self.cancellation {
+            Some(token) if token.is_cancelled() => Err(WaitError::Cancelled),
+            _ if self.beats.iter().any(|beats| beats.stopped.get()) => Err(WaitError::Cancelled),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sleeps for the polling interval or until the deadline whichever comes first.
+    ///
+    /// Returns an error if the deadline was already reached or if the wait is cancelled while
+    /// sleeping.
+    fn sleep(&self, interval: Duration) -> Result<(), WaitError> {
+        let mut remaining = match self.deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(self.clock.now());
+                if remaining == Duration::from_secs(0) {
+                    return Err(self.timed_out());
+                }
+                interval.min(remaining)
+            },
+            None => interval,
+        };
+        // Sleeping is split at the heartbeats
+        loop {
+            let duration = match self.next_beat() {
+                Some(next_beat) => remaining.min(next_beat.saturating_duration_since(self.clock.now())),
+                None => remaining,
+            };
+            match self.cancellation {
+                Some(token) => if token.sleep(duration) {
+                    return Err(WaitError::Cancelled);
+                },
+                None => self.clock.sleep(duration),
+            }
+            remaining -= duration;
+            self.beat();
+            self.check_cancelled()?;
+            if remaining == Duration::from_secs(0) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Calls the periodic callbacks which are due.
+    fn beat(&self) {
+        let now = self.clock.now();
+        for beats in self.beats.iter().filter(|beats| now >= beats.next.get()) {
+            if beats.callback.borrow_mut()().is_break() {
+                beats.stopped.set(true);
+            }
+            beats.next.set(now + beats.interval);
+        }
+    }
+
+    /// Returns the time of the earliest periodic callback.
+    fn next_beat(&self) -> Option<Instant> {
+        self.beats.iter().map(|beats| beats.next.get()).min()
+    }
+
+    /// Returns the time until the deadline or the next heartbeat whichever comes first.
+    ///
+    /// Returns `None` if waiting is not limited.
+    fn wake_after(&self) -> Option<Duration> {
+        let next_beat = self.next_beat();
+        let wake_at = match (self.deadline, next_beat) {
+            (Some(deadline), Some(next_beat)) => Some(deadline.min(next_beat)),
+            (deadline, next_beat) => deadline.or(next_beat),
+        };
+        wake_at.map(|wake_at| wake_at.saturating_duration_since(self.clock.now()))
+    }
+
+    /// Blocks until the source has changes to read, the deadline is reached or the wait is
+    /// cancelled.
+    #[cfg(unix)]
+    fn wait_readable<W: ChangeSource + ?Sized>(&self, source: &W) -> io::Result<Wakeup> {
+        let mut pollfds = [
+            libc::pollfd {
+                fd: source.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: self.cancellation.map_or(-1, |token| token.inner.pipe_read.as_raw_fd()),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+
+        loop {
+            // The callbacks may stop waiting
+            self.beat();
+            if self.check_cancelled().is_err() {
+                return Ok(Wakeup::Cancelled);
+            }
+
+            if self.is_expired() {
+                return Ok(Wakeup::TimedOut);
+            }
+
+            let timeout = match self.wake_after() {
+                Some(remaining) => {
+                    // Round up to avoid spinning when less than a millisecond remains
+                    let millis = remaining.as_nanos().div_ceil(1_000_000);
+                    millis.min(libc::c_int::MAX as u128) as libc::c_int
+                },
+                None => -1,
+            };
+
+            // SAFETY: the pointer and length come from a valid array.
+            // Negative fds are ignored by poll().
+            match unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, timeout) } {
+                -1 => {
+                    let error = io::Error::last_os_error();
+                    if error.kind() != io::ErrorKind::Interrupted {
+                        return Err(error);
+                    }
+                },
+                // Timed out, the loop will check the deadline again
+                0 => (),
+                _ if pollfds[1].revents != 0 => return Ok(Wakeup::Cancelled),
+                _ => return Ok(Wakeup::Readable),
+            }
+        }
+    }
+
+    /// Blocks until the source has changes to read, the deadline is reached or the wait is
+    /// cancelled.
+    #[cfg(windows)]
+    fn wait_readable<W: ChangeSource + ?Sized>(&self, source: &W) -> io::Result<Wakeup> {
+        let cancel_event = self.cancellation.map(|token| token.inner.event.as_raw_handle());
+        let handles = [source.as_raw_handle(), cancel_event.unwrap_or_else(|| source.as_raw_handle())];
+        let handles = &handles[..1 + usize::from(cancel_event.is_some())];
+
+        loop {
+            // The callbacks may stop waiting
+            self.beat();
+            if self.check_cancelled().is_err() {
+                return Ok(Wakeup::Cancelled);
+            }
+
+            if self.is_expired() {
+                return Ok(Wakeup::TimedOut);
+            }
+
+            let timeout = self.wake_after().map(|remaining| {
+                // Round up to avoid spinning when less than a millisecond remains
+                let millis = remaining.as_nanos().div_ceil(1_000_000);
+                millis.min(u32::MAX.into()) as u32
+            });
+
+            match backend::wait_any(handles, timeout)? {
+                // Timed out, the loop will check the deadline again
+                None => (),
+                Some(1) => return Ok(Wakeup::Cancelled),
+                Some(_) => return Ok(Wakeup::Readable),
+            }
+        }
+    }
+}
+
+/// Reads changes looking for those concerning the file with the given name.
+///
+/// `file_name` must be the base name of the file since the changes only carry names of the
+/// entries within the watched directory, never the whole path.
+///
+/// Returns an error if the watch was removed or `ErrorKind::WouldBlock` if there were no changes.
+fn scan_changes<W: ChangeSource>(watcher: &mut W, buffer: &mut [u8], file_name: &OsStr) -> io::Result<Option<Readiness>> {
+    let mut found = None;
+    let mut removed = false;
+    watcher.read_changes(buffer, |_, change| match change {
+        // Deletions are only watched for `Watcher::wait_event`
+        Change::Entry(_, CreationKind::Deleted) => (),
+        Change::Entry(name, kind) if name == file_name => found = Some(Readiness::Available(kind)),
+        Change::Entry(_, _) => (),
+        Change::Unknown | Change::Overflow => if found.is_none() {
+            found = Some(Readiness::Maybe);
+        },
+        Change::Removed => removed = true,
+    })?;
+
+    if removed {
+        return Err(io::Error::from(io::ErrorKind::NotFound));
+    }
+    Ok(found)
+}
+
+/// Converts the result of an attempt to the input of `WaitState` keeping the value or the error.
+fn open_result<T>(result: io::Result<Option<T>>, last: &mut Option<io::Result<T>>) -> OpenResult {
+    match result {
+        Ok(Some(value)) => {
+            *last = Some(Ok(value));
+            OpenResult::Opened
+        },
+        Ok(None) => {
+            *last = None;
+            OpenResult::NotReady
+        },
+        Err(error) => {
+            let result = if error.kind() == io::ErrorKind::NotFound { OpenResult::NotFound } else { OpenResult::Failed };
+            *last = Some(Err(error));
+            result
+        },
+    }
+}
+
+/// Returns the value of the last successful attempt.
+fn take_opened<T>(last: &mut Option<io::Result<T>>) -> T {
+    match last.take() {
+        Some(Ok(value)) => value,
+        _ => panic!("the state machine returned a file that wasn't opened"),
+    }
+}
+
+/// Returns the error of the last failed attempt converted according to the outcome.
+fn take_error<T>(last: &mut Option<io::Result<T>>, outcome: WaitOutcome) -> WaitError {
+    let error = match last.take() {
+        Some(Err(error)) => error,
+        _ => panic!("the state machine returned an error that didn't happen"),
+    };
+    match outcome {
+        WaitOutcome::FlukeRetriesExhausted => WaitError::FlukeRetriesExhausted(error),
+        _ => WaitError::Open(error),
+    }
+}
+
+/// Attempts to open a file after `notification` letting the state decide about flukes.
+///
+/// This drives a single step of `WaitState` for the loops checking multiple files using one state.
+/// Returns `None` if the file isn't ready and waiting should continue.
+fn check_notified<T>(state: &mut WaitState, notification: Notification, context: &WaitContext<'_>, attempt: impl FnOnce(CreationKind) -> io::Result<Option<T>>) -> Result<Option<T>, WaitError> {
+    let kind = match state.notify(notification) {
+        WaitAction::Open(kind) => kind,
+        action => unreachable!("a notification resulted in {:?}", action),
+    };
+    let mut last = None;
+    match state.open_result(open_result(attempt(kind), &mut last)) {
+        WaitAction::Return(WaitOutcome::Opened(_)) => Ok(Some(take_opened(&mut last))),
+        WaitAction::Return(outcome) => Err(take_error(&mut last, outcome)),
+        WaitAction::Sleep(cooldown) => {
+            context.sleep(cooldown)?;
+            state.slept();
+            Ok(None)
+        },
+        WaitAction::Wait => Ok(None),
+        WaitAction::Open(_) => unreachable!("opening the file is never followed by opening it again"),
+    }
+}
+
+/// Sleeps before the next polling round as decided by the state.
+///
+/// `round` summarizes the attempts of the previous round, `OpenResult::NotReady` if any of the
+/// files exists but isn't ready. Returns `false` if polling is disabled.
+fn next_poll(state: &mut WaitState, round: OpenResult, context: &WaitContext<'_>) -> Result<bool, WaitError> {
+    match state.open_result(round) {
+        WaitAction::Sleep(delay) => {
+            context.sleep(delay)?;
+            state.slept();
+            Ok(true)
+        },
+        WaitAction::Return(WaitOutcome::Unavailable) => Ok(false),
+        action => unreachable!("polling resulted in {:?}", action),
+    }
+}
+
+/// Returns the size and modification time used to detect whether the file is still written to.
+fn file_state(path: &Path) -> io::Result<(u64, Option<std::time::SystemTime>)> {
+    let metadata = std::fs::metadata(path)?;
+    Ok((metadata.len(), metadata.modified().ok()))
+}
+
+#[cfg(unix)]
+fn set_cloexec(fd: RawFd) -> io::Result<()> {
+    // SAFETY: fcntl with F_GETFD/F_SETFD doesn't touch memory
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        if flags == -1 || libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_nonblocking(fd: RawFd) -> io::Result<()> {
+    // SAFETY: fcntl with F_GETFL/F_SETFL doesn't touch memory
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags == -1 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Splits the path into the directory that should be watched and the name of the file.
+///
+/// Returns `None` if the path doesn't end with a file name (e.g. `/` or `..`).
+fn split_path(path: &Path) -> Option<(&Path, &OsStr)> {
+    let file_name = path.file_name()?;
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    Some((dir, file_name))
+}
+
+/// Returns an error if `name` is not a single normal path component.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn check_single_component(name: &OsStr) -> Result<(), WaitError> {
+    use std::path::Component;
+
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(component)), None) if component == name => Ok(()),
+        _ => Err(WaitError::Open(io::Error::new(io::ErrorKind::InvalidInput, "the name must be a single path component"))),
+    }
+}
+
+#[cfg(unix)]
+fn symlink_error() -> io::Error {
+    io::Error::from_raw_os_error(libc::ELOOP)
+}
+
+#[cfg(windows)]
+fn symlink_error() -> io::Error {
+    io::Error::from_raw_os_error(backend::ERROR_CANT_RESOLVE_FILENAME)
+}
+
+#[cfg(unix)]
+fn not_directory_error() -> io::Error {
+    io::Error::from_raw_os_error(libc::ENOTDIR)
+}
+
+#[cfg(windows)]
+fn not_directory_error() -> io::Error {
+    io::Error::from_raw_os_error(backend::ERROR_DIRECTORY)
+}
+
+/// The longest file name supported by common file systems.
+///
+/// Measured in bytes on Unix and in UTF-16 code units on Windows.
+const NAME_MAX: usize = 255;
+
+/// Rejects file names too long to be ever created which would make waiting for them endless.
+fn check_name_len(path: &Path) -> Result<(), WaitError> {
+    let name = match path.file_name() {
+        Some(name) => name,
+        None => return Ok(()),
+    };
+    #[cfg(unix)]
+    let len = name.len();
+    #[cfg(windows)]
+    let len = std::os::windows::ffi::OsStrExt::encode_wide(name).count();
+
+    if len > NAME_MAX {
+        let message = format!("the file name is {} long but at most {} is supported", len, NAME_MAX);
+        return Err(WaitError::Open(io::Error::new(io::ErrorKind::InvalidInput, message)));
+    }
+    Ok(())
+}
+
+fn inotify_disabled() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "inotify was disabled by polling_only")
+}
+
+thread_local! {
+    // The error of the last attempt to open a file which was retried instead of failing
+    static RETRIED_ERROR: Cell<Option<io::Error>> = const { Cell::new(None) };
+}
+
+/// Remembers the error of an attempt which is going to be retried so polling can report it.
+fn record_retried<T>(error: io::Error) -> Option<T> {
+    RETRIED_ERROR.with(|retried| retried.set(Some(error)));
+    None
+}
+
+/// Returns the error recorded by `record_retried` since the last call.
+fn take_retried() -> Option<io::Error> {
+    RETRIED_ERROR.with(Cell::take)
+}
+
+/// Retries the operation for as long as it's interrupted by a signal.
+///
+/// `std` retries some syscalls itself but it doesn't guarantee it.
+fn retry_interrupted<T, F: FnMut() -> io::Result<T>>(mut f: F) -> io::Result<T> {
+    loop {
+        match f() {
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => (),
+            result => return result,
+        }
+    }
+}
+
+/// Returns an error if the opened file is a directory.
+fn reject_directory(file: File) -> io::Result<File> {
+    if file.metadata()?.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::IsADirectory, "expected a file but found a directory"));
+    }
+    Ok(file)
+}
+
+fn timed_out() -> io::Error {
+    WaitError::TimedOut { saw_create: false, saw_close_write: false }.into()
+}
+
+fn cancelled() -> io::Error {
+    WaitError::Cancelled.into()
+}
+
+/// Polling interval used by the shorthand functions.
+///
+/// Use it when building `Options` which should behave the same as the shorthand functions.
+pub const DEFAULT_POLLING_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Delay between reads when the watcher keeps being readable without any changes to read.
+const SPURIOUS_WAKEUP_DELAY: Duration = Duration::from_millis(10);
+
+/// Wait for file being available without opening it falling back on some errors.
+///
+/// If `inotify` is unavailable this will poll every 2 seconds.
+///
+/// This is a shorthand for creating `Options`, setting `retry_on_fluke` to `true` and
+/// `polling_fallback_interval` to two seconds then calling `wait_only`.
+///
+/// Note that the file may be deleted or replaced right after this function returns. Prefer the
+/// functions returning an opened file if you can.
+pub fn wait_until_created<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    Options::with_open_options(OpenOptions::new())
+        .retry_on_fluke(true)
+        .polling_fallback_interval(DEFAULT_POLLING_INTERVAL)
+        .wait_only(path)
+}
+
+/// Wait for file being deleted falling back on some errors.
+///
+/// If `inotify` is unavailable this will poll every 2 seconds.
+///
+/// This is a shorthand for creating `Options`, setting `polling_fallback_interval` to two seconds
+/// then calling `wait_until_deleted`.
+///
+/// Note that the file may be created again right after this function returns.
+pub fn wait_until_deleted<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    Options::with_open_options(OpenOptions::new())
+        .polling_fallback_interval(DEFAULT_POLLING_INTERVAL)
+        .wait_until_deleted(path)
+}
+
+/// Wait for all files being available and open them for reading falling back on some errors.
+///
+/// If `inotify` is unavailable this will poll every 2 seconds.
+///
+/// This is a shorthand for creating `Options`, setting `retry_on_fluke` to `true` and
+/// `polling_fallback_interval` to two seconds then calling `open_all_when_created`. The files are
+/// returned in the same order as `paths`.
+pub fn wait_all_created(paths: &[&Path]) -> io::Result<Vec<File>> {
+    let mut open_options = OpenOptions::new();
+    open_options.read(true);
+
+    Options::with_open_options(open_options)
+        .retry_on_fluke(true)
+        .polling_fallback_interval(DEFAULT_POLLING_INTERVAL)
+        .open_all_when_created(paths)?
+        .into_iter()
+        .map(reject_directory)
+        .collect()
+}
+
+/// Wait for all files being available and open them for reading one by one.
+///
+/// This is the same as `wait_all_created` except that the files are closed after checking that
+/// they exist and opened again only when the returned iterator reaches them. See
+/// `Options::open_all_when_created_lazy` for the implications.
+pub fn wait_all_created_lazy<'a>(paths: &'a [&'a Path]) -> io::Result<impl Iterator<Item = (PathBuf, io::Result<File>)> + 'a> {
+    let mut open_options = OpenOptions::new();
+    open_options.read(true);
+
+    let options = Options::with_open_options(open_options)
+        .retry_on_fluke(true)
+        .polling_fallback_interval(DEFAULT_POLLING_INTERVAL);
+    options.wait_all_ready(paths)?;
+    Ok(paths.iter().map(move |path| (path.to_path_buf(), options.open_ready(path).and_then(reject_directory))))
+}
+
+/// Wait for directory being available falling back on some errors.
+///
+/// If `inotify` is unavailable this will poll every 2 seconds.
+///
+/// This is a shorthand for creating `Options`, setting `expect_directory` and `retry_on_fluke` to
+/// `true` and `polling_fallback_interval` to two seconds then calling `wait_only`.
+pub fn wait_dir_created<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    Options::with_open_options(OpenOptions::new())
+        .expect_directory(true)
+        .retry_on_fluke(true)
+        .polling_fallback_interval(DEFAULT_POLLING_INTERVAL)
+        .wait_only(path)
+}
+
+/// Wait for file being available and open it for reading once it is falling back on some errors.
+///
+/// If `inotify` is unavailable this will poll every 2 seconds.
+///
+/// This is a shorthand for creating `Options`, setting `retry_on_fluke` to `true` and
+/// `polling_fallback_interval` to two seconds then calling `open_when_created`.
+///
+/// Opening a directory for reading succeeds on some platforms so if a directory is created at
+/// the path an error with `ErrorKind::IsADirectory` is returned instead of a useless handle. The
+/// other shorthands opening files only for reading reject directories as well: `wait_read_to_end`,
+/// `wait_read_to_string`, `wait_buf_read`, `wait_lines`, `wait_all_created`,
+/// `wait_all_created_lazy` and `wait_for_linked_tmpfile`.
+pub fn robust_wait_read<P: AsRef<Path>>(path: P) -> io::Result<File> {
+    let mut open_options = OpenOptions::new();
+    open_options.read(true);
+
+    let file = Options::with_open_options(open_options)
+        .retry_on_fluke(true)
+        .polling_fallback_interval(DEFAULT_POLLING_INTERVAL)
+        .open_when_created(path)?;
+    reject_directory(file)
+}
+
+/// Wait for file being available and open it for reading and writing once it is falling back on some errors.
+///
+/// If `inotify` is unavailable this will poll every 2 seconds.
+///
+/// This is a shorthand for creating `Options`, setting `retry_on_fluke` to `true` and
+/// `polling_fallback_interval` to two seconds then calling `open_when_created`.
+pub fn robust_wait_read_write<P: AsRef<Path>>(path: P) -> io::Result<File> {
+    let mut open_options = OpenOptions::new();
+    open_options.read(true).write(true);
+
+    Options::with_open_options(open_options)
+        .retry_on_fluke(true)
+        .polling_fallback_interval(DEFAULT_POLLING_INTERVAL)
+        .open_when_created(path)
+}
+
+/// Open the file for reading and writing creating it if it doesn't exist yet.
+///
+/// This is useful for coordinating multiple applications where the first one to start creates
+/// the file and the others open it. Since opening creates the file there is no waiting at all -
+/// the function returns as soon as the file is opened or created. Use `robust_wait_read_write`
+/// if the file must be created by someone else.
+///
+/// This is a shorthand for creating `Options` with `OpenOptions` having `read`, `write` and
+/// `create` set to `true`, setting `retry_on_fluke` to `true` and `polling_fallback_interval` to
+/// two seconds then calling `open_when_created`.
+pub fn robust_wait_read_write_create<P: AsRef<Path>>(path: P) -> io::Result<File> {
+    let mut open_options = OpenOptions::new();
+    open_options.read(true).write(true).create(true);
+
+    Options::with_open_options(open_options)
+        .retry_on_fluke(true)
+        .polling_fallback_interval(DEFAULT_POLLING_INTERVAL)
+        .open_when_created(path)
+}
+
+/// Wait for file being available and open it for reading and appending once it is falling back on some errors.
+///
+/// If `inotify` is unavailable this will poll every 2 seconds.
+///
+/// This is a shorthand for creating `Options`, setting `retry_on_fluke` to `true` and
+/// `polling_fallback_interval` to two seconds then calling `open_when_created`.
+///
+/// The returned file keeps referring to the same file even if the producer replaces it. Use
+/// `Watcher::reopen_on_replace` to switch to the new file in such case.
+pub fn robust_wait_read_append<P: AsRef<Path>>(path: P) -> io::Result<File> {
+    let mut open_options = OpenOptions::new();
+    open_options.read(true).append(true);
+
+    Options::with_open_options(open_options)
+        .retry_on_fluke(true)
+        .polling_fallback_interval(DEFAULT_POLLING_INTERVAL)
+        .open_when_created(path)
+}
+
+/// Wait for file created using the *`O_TMPFILE` method* and open it for reading.
+///
+/// If `inotify` is unavailable this will poll every 2 seconds.
+///
+/// Producers using `O_TMPFILE` populate an anonymous file and then link it to the directory using
+/// `linkat()` which is reported as creation of the file. The file is opened right when it's linked
+/// so the producer may keep its file descriptor open. Files renamed into place are opened as well.
+///
+/// This is a shorthand for creating `Options`, setting `assume_create_is_atomic` and
+/// `retry_on_fluke` to `true` and `polling_fallback_interval` to two seconds then calling
+/// `open_when_created`.
+pub fn wait_for_linked_tmpfile<P: AsRef<Path>>(path: P) -> io::Result<File> {
+    let mut open_options = OpenOptions::new();
+    open_options.read(true);
+
+    let file = Options::with_open_options(open_options)
+        .assume_create_is_atomic(true)
+        .retry_on_fluke(true)
+        .polling_fallback_interval(DEFAULT_POLLING_INTERVAL)
+        .open_when_created(path)?;
+    reject_directory(file)
+}
+
+/// Wait for file being available and read all its bytes falling back on some errors.
+///
+/// If `inotify` is unavailable this will poll every 2 seconds.
+///
+/// This is a shorthand for `robust_wait_read` followed by reading the whole file.
+pub fn wait_read_to_end<P: AsRef<Path>>(path: P) -> io::Result<Vec<u8>> {
+    use std::io::Read;
+
+    let mut contents = Vec::new();
+    robust_wait_read(path)?.read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+/// Wait for file being available and read its contents as a string falling back on some errors.
+///
+/// If `inotify` is unavailable this will poll every 2 seconds.
+///
+/// This is a shorthand for `robust_wait_read` followed by reading the whole file.
+/// An error with `ErrorKind::InvalidData` is returned if the contents are not valid UTF-8.
+pub fn wait_read_to_string<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    use std::io::Read;
+
+    let mut contents = String::new();
+    robust_wait_read(path)?.read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+/// Wait for file being available and open it for buffered reading falling back on some errors.
+///
+/// If `inotify` is unavailable this will poll every 2 seconds.
+///
+/// This is a shorthand for `robust_wait_read` followed by wrapping the file in `BufReader`.
+pub fn wait_buf_read<P: AsRef<Path>>(path: P) -> io::Result<io::BufReader<File>> {
+    robust_wait_read(path).map(io::BufReader::new)
+}
+
+/// Wait for file being available and iterate over its lines falling back on some errors.
+///
+/// If `inotify` is unavailable this will poll every 2 seconds.
+///
+/// This is a shorthand for `wait_buf_read` followed by calling `lines()`. This is convenient for
+/// line-oriented marker files. Each line is read lazily and fails with `ErrorKind::InvalidData` if
+/// it's not valid UTF-8.
+pub fn wait_lines<P: AsRef<Path>>(path: P) -> io::Result<io::Lines<io::BufReader<File>>> {
+    use std::io::BufRead;
+
+    wait_buf_read(path).map(BufRead::lines)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_wait() {
+        use std::io::{Read, Write};
+
+        let test_string = "satoshi nakamoto";
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            // Sleeps between operations should make it easier to detect inconsistencies
+            std::thread::sleep(std::time::Duration::from_secs(2));
+            let mut file = std::fs::File::create(&file_path_thread).unwrap();
+            std::thread::sleep(std::time::Duration::from_secs(1));
+            file.write_all(test_string.as_bytes()).unwrap();
+            std::thread::sleep(std::time::Duration::from_secs(1));
+        });
+        let mut file = super::robust_wait_read(&file_path).unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, test_string);
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn timeout() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let start = std::time::Instant::now();
+        let error = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .timeout(std::time::Duration::from_millis(200))
+            .open_when_created(&file_path)
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < std::time::Duration::from_secs(2));
+    }
+
+    #[test]
+    fn deadline() {
+        use std::time::{Duration, Instant};
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let first = temp_dir.join("first");
+        std::fs::write(&first, "satoshi nakamoto").unwrap();
+        let start = Instant::now();
+        // The deadline is earlier so it applies
+        let options = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .timeout(Duration::from_secs(60))
+            .deadline(start + Duration::from_millis(200));
+        options.open_when_created(&first).unwrap();
+        let error = options.open_when_created(temp_dir.join("second")).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn timeout_progress() {
+        use super::WaitError;
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let options = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .wait_for_nonempty(true)
+            .timeout(std::time::Duration::from_millis(300));
+        let error = options.try_open_when_created(&file_path).unwrap_err();
+        assert!(matches!(error, WaitError::TimedOut { saw_create: false, saw_close_write: false }));
+
+        // The empty file is not ready
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::File::create(&file_path_thread).unwrap();
+        });
+        let error = options.try_open_when_created(&file_path).unwrap_err();
+        assert!(matches!(error, WaitError::TimedOut { saw_create: true, saw_close_write: true }));
+        thread.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn existing_file_reads_no_changes() {
+        use std::os::unix::io::{AsRawFd, RawFd};
+        use super::ChangeSource;
+
+        struct CountingSource {
+            watcher: super::backend::DirWatcher,
+            reads: usize,
+        }
+
+        impl AsRawFd for CountingSource {
+            fn as_raw_fd(&self) -> RawFd {
+                self.watcher.as_raw_fd()
+            }
+        }
+
+        impl ChangeSource for CountingSource {
+            fn read_changes<F: FnMut(&super::backend::WatchId, super::Change<'_>)>(&mut self, buffer: &mut [u8], f: F) -> std::io::Result<()> {
+                self.reads += 1;
+                self.watcher.read_changes(buffer, f)
+            }
+        }
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        std::fs::write(&file_path, "satoshi nakamoto").unwrap();
+        let options = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone());
+        let mut watcher = super::backend::DirWatcher::new(&options).unwrap();
+        watcher.watch(&temp_dir).unwrap();
+        let mut source = CountingSource { watcher, reads: 0 };
+        let context = options.wait_context(None);
+        options.wait_for_file(&mut source, &mut super::EventBuffer::new(&options), &file_path, "test".as_ref(), &context, &|path, kind| options.attempt_open(path, kind)).unwrap();
+        // The first open after setting up the watch succeeded so no changes were read
+        assert_eq!(source.reads, 0);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn readable_without_changes_does_not_spin() {
+        use std::os::unix::io::{AsRawFd, RawFd};
+        use super::ChangeSource;
+
+        // Always readable but never has any changes
+        struct EmptySource {
+            null: std::fs::File,
+            reads: usize,
+        }
+
+        impl AsRawFd for EmptySource {
+            fn as_raw_fd(&self) -> RawFd {
+                self.null.as_raw_fd()
+            }
+        }
+
+        impl ChangeSource for EmptySource {
+            fn read_changes<F: FnMut(&super::backend::WatchId, super::Change<'_>)>(&mut self, _buffer: &mut [u8], _f: F) -> std::io::Result<()> {
+                self.reads += 1;
+                Err(std::io::ErrorKind::WouldBlock.into())
+            }
+        }
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let options = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .timeout(std::time::Duration::from_millis(200));
+        let mut source = EmptySource { null: std::fs::File::open("/dev/null").unwrap(), reads: 0 };
+        let context = options.wait_context(None);
+        let error = options.wait_for_file(&mut source, &mut super::EventBuffer::new(&options), &file_path, "test".as_ref(), &context, &|path, kind| options.attempt_open(path, kind)).unwrap_err();
+        assert!(matches!(error, super::WaitError::TimedOut { .. }));
+        // Roughly one read per `SPURIOUS_WAKEUP_DELAY`, spinning would do many thousands
+        assert!(source.reads < 50, "{} reads", source.reads);
+    }
+
+    #[test]
+    fn heartbeat() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+
+        let options = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone());
+        let polling = options.clone().polling_only(Duration::from_millis(35));
+
+        for options in [options, polling] {
+            let temp_dir = mktemp::Temp::new_dir().unwrap();
+            let file_path = temp_dir.join("test");
+            let file_path_thread = file_path.clone();
+            let thread = std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(200));
+                std::fs::write(&file_path_thread, "satoshi nakamoto").unwrap();
+            });
+            let beats = Arc::new(AtomicUsize::new(0));
+            let beats_callback = beats.clone();
+            options
+                .heartbeat(Duration::from_millis(20), move || { beats_callback.fetch_add(1, Ordering::Relaxed); })
+                .unwrap()
+                .open_when_created(&file_path)
+                .unwrap();
+            thread.join().unwrap();
+            let count = beats.load(Ordering::Relaxed);
+            assert!(count >= 3, "only {} heartbeats", count);
+            // Stopped once the file was opened
+            std::thread::sleep(Duration::from_millis(50));
+            assert_eq!(beats.load(Ordering::Relaxed), count);
+        }
+
+        let error = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .heartbeat(Duration::ZERO, || ())
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn timeout_polling() {
+        // The parent doesn't exist so adding the watch fails and we fall back to polling.
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("missing").join("test");
+        let start = std::time::Instant::now();
+        let error = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .polling_fallback_interval(std::time::Duration::from_secs(10))
+            .timeout(std::time::Duration::from_millis(200))
+            .open_when_created(&file_path)
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < std::time::Duration::from_secs(2));
+    }
+
+    #[test]
+    fn clone_and_debug() {
+        let template = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .retry_on_fluke(true);
+        let options = template.clone().polling_fallback_interval(std::time::Duration::from_secs(2));
+        let debug = format!("{:?}", options);
+        assert!(debug.contains("retry_flukes: true"));
+        assert!(debug.contains("polling_fallback: Some"));
+        assert!(format!("{:?}", template).contains("polling_fallback: None"));
+    }
+
+    #[test]
+    fn backoff() {
+        use std::time::Duration;
+
+        let polling = super::Polling::new(Duration::from_millis(10), Duration::from_millis(40), 2.0);
+        let mut backoff = super::state::Backoff::new(polling);
+        assert_eq!(backoff.next_delay(), Duration::from_millis(10));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(20));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(40));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(40));
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_millis(10));
+
+        let mut constant = super::state::Backoff::new(super::Polling::constant(Duration::from_secs(2)));
+        assert_eq!(constant.next_delay(), Duration::from_secs(2));
+        assert_eq!(constant.next_delay(), Duration::from_secs(2));
+
+        let clamped = super::Polling::new(Duration::from_secs(5), Duration::from_secs(1), 2.0);
+        assert_eq!(clamped.initial(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn cancel() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let token = super::CancellationToken::new().unwrap();
+        let token_thread = token.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            token_thread.cancel();
+        });
+        let start = std::time::Instant::now();
+        let error = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .open_when_created_cancellable(&file_path, &token)
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::Interrupted);
+        assert!(start.elapsed() < std::time::Duration::from_secs(2));
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn open_when_created_with_tick() {
+        use std::ops::ControlFlow;
+        use std::time::Duration;
+
+        let options = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone());
+        let polling = options.clone().polling_only(Duration::from_millis(35));
+
+        for options in [options, polling] {
+            let temp_dir = mktemp::Temp::new_dir().unwrap();
+            let file_path = temp_dir.join("test");
+            let file_path_thread = file_path.clone();
+            let thread = std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(200));
+                std::fs::write(&file_path_thread, "satoshi nakamoto").unwrap();
+            });
+            let mut ticks = 0;
+            options.open_when_created_with_tick(&file_path, Duration::from_millis(20), || {
+                ticks += 1;
+                ControlFlow::Continue(())
+            }).unwrap();
+            thread.join().unwrap();
+            assert!(ticks >= 3, "only {} ticks", ticks);
+
+            // Stops right after the tick breaks
+            let mut ticks = 0;
+            let error = options.open_when_created_with_tick(temp_dir.join("missing"), Duration::from_millis(20), || {
+                ticks += 1;
+                if ticks == 3 { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+            }).unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::Interrupted);
+            assert_eq!(ticks, 3);
+
+            let error = options.open_when_created_with_tick(temp_dir.join("missing"), Duration::ZERO, || ControlFlow::Continue(())).unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn cancel_polling() {
+        use std::time::Duration;
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let token = super::CancellationToken::new().unwrap();
+        let token_thread = token.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            token_thread.cancel();
+        });
+        let start = std::time::Instant::now();
+        let error = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .polling_only(Duration::from_secs(10))
+            .open_when_created_cancellable(&file_path, &token)
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::Interrupted);
+        assert!(start.elapsed() < Duration::from_secs(1));
+        thread.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn creation_kind() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let tmp_path = temp_dir.join("test.tmp");
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(&tmp_path, "satoshi nakamoto").unwrap();
+            std::fs::rename(&tmp_path, &file_path_thread).unwrap();
+        });
+        let options = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone());
+        let (_, kind) = options.open_when_created_with_event(&file_path).unwrap();
+        assert_eq!(kind, super::CreationKind::MovedTo);
+        thread.join().unwrap();
+
+        let (_, kind) = options.open_when_created_with_event(&file_path).unwrap();
+        assert_eq!(kind, super::CreationKind::NoEvent);
+    }
+
+    #[test]
+    fn reporting_preexisting() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(&file_path_thread, "satoshi nakamoto").unwrap();
+        });
+        let options = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone());
+        let polling = options.clone().polling_only(std::time::Duration::from_millis(10));
+        let (_, preexisting) = options.open_when_created_reporting_preexisting(&file_path).unwrap();
+        assert!(!preexisting);
+        thread.join().unwrap();
+
+        for options in [options, polling] {
+            let (_, preexisting) = options.open_when_created_reporting_preexisting(&file_path).unwrap();
+            assert!(preexisting);
+        }
+    }
+
+    #[test]
+    fn nested_path_event() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let dir = temp_dir.join("subdir");
+        std::fs::create_dir(&dir).unwrap();
+        let file_path = dir.join("target");
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(&file_path_thread, "satoshi nakamoto").unwrap();
+        });
+        // No polling or rechecks so only the event can unblock the wait
+        let options = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone());
+        let (_, kind) = options.open_when_created_with_event(&file_path).unwrap();
+        assert_ne!(kind, super::CreationKind::NoEvent);
+        thread.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn moved_from_other_directory() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let tmp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let tmp_path = tmp_dir.join("test");
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(&tmp_path, "satoshi nakamoto").unwrap();
+            std::fs::rename(&tmp_path, &file_path_thread).unwrap();
+        });
+        let options = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone());
+        let (_, kind) = options.open_when_created_with_event(&file_path).unwrap();
+        assert_eq!(kind, super::CreationKind::MovedTo);
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn wait_until_created() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(&file_path_thread, "satoshi nakamoto").unwrap();
+        });
+        super::wait_until_created(&file_path).unwrap();
+        assert!(file_path.exists());
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn wait_read_to_string() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(&file_path_thread, "satoshi nakamoto").unwrap();
+        });
+        assert_eq!(super::wait_read_to_string(&file_path).unwrap(), "satoshi nakamoto");
+        assert_eq!(super::wait_read_to_end(&file_path).unwrap(), b"satoshi nakamoto");
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn read_shorthands_reject_directory() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let dir_path = temp_dir.join("test");
+        let dir_path_thread = dir_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            // Creating a directory isn't watched by default, moving it is
+            let tmp_path = dir_path_thread.with_extension("tmp");
+            std::fs::create_dir(&tmp_path).unwrap();
+            std::fs::rename(&tmp_path, &dir_path_thread).unwrap();
+        });
+        let error = super::robust_wait_read(&dir_path).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::IsADirectory);
+        thread.join().unwrap();
+        assert_eq!(super::wait_read_to_end(&dir_path).unwrap_err().kind(), std::io::ErrorKind::IsADirectory);
+        assert_eq!(super::wait_lines(&dir_path).unwrap_err().kind(), std::io::ErrorKind::IsADirectory);
+        assert_eq!(super::wait_for_linked_tmpfile(&dir_path).unwrap_err().kind(), std::io::ErrorKind::IsADirectory);
+        assert_eq!(super::wait_all_created(&[&dir_path]).unwrap_err().kind(), std::io::ErrorKind::IsADirectory);
+        let (_, result) = super::wait_all_created_lazy(&[&dir_path]).unwrap().next().unwrap();
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::IsADirectory);
+    }
+
+    #[test]
+    fn wait_lines() {
+        use std::io::BufRead;
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(&file_path_thread, "satoshi\nnakamoto\n").unwrap();
+        });
+        let lines = super::wait_lines(&file_path).unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(lines, ["satoshi", "nakamoto"]);
+        let mut first = String::new();
+        super::wait_buf_read(&file_path).unwrap().read_line(&mut first).unwrap();
+        assert_eq!(first, "satoshi\n");
+        thread.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn wait_for_linked_tmpfile() {
+        use std::io::{Read, Write};
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::fs::OpenOptionsExt;
+        use std::os::unix::io::AsRawFd;
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let file_path_thread = file_path.clone();
+        let dir_thread = temp_dir.to_path_buf();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            let mut file = std::fs::OpenOptions::new()
+                .write(true)
+                .custom_flags(libc::O_TMPFILE)
+                .open(&dir_thread)
+                .unwrap();
+            file.write_all(b"satoshi nakamoto").unwrap();
+            let source = std::ffi::CString::new(format!("/proc/self/fd/{}", file.as_raw_fd())).unwrap();
+            let target = std::ffi::CString::new(file_path_thread.as_os_str().as_bytes()).unwrap();
+            // SAFETY: both paths are valid NUL-terminated strings
+            let result = unsafe { libc::linkat(libc::AT_FDCWD, source.as_ptr(), libc::AT_FDCWD, target.as_ptr(), libc::AT_SYMLINK_FOLLOW) };
+            assert_eq!(result, 0);
+            // The file must be opened while the descriptor is still open
+            receiver.recv_timeout(std::time::Duration::from_secs(5)).unwrap();
+        });
+        let mut file = super::wait_for_linked_tmpfile(&file_path).unwrap();
+        let _ = sender.send(());
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "satoshi nakamoto");
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn already_exists() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        std::fs::write(&file_path, "satoshi nakamoto").unwrap();
+        let (_, kind) = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .open_when_created_with_event(&file_path)
+            .unwrap();
+        assert_eq!(kind, super::CreationKind::NoEvent);
+    }
+
+    #[test]
+    fn open_when_created_timed() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(&file_path_thread, "satoshi nakamoto").unwrap();
+        });
+        let (_, elapsed) = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .open_when_created_timed(&file_path)
+            .unwrap();
+        assert!(elapsed >= std::time::Duration::from_millis(100));
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn event_buffer_size() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let options = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone());
+        assert_eq!(options.clone().event_buffer_size(16).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        let options = options.event_buffer_size(65536).unwrap();
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(&file_path_thread, "satoshi nakamoto").unwrap();
+        });
+        options.open_when_created(&file_path).unwrap();
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn robust_wait_read_write_create() {
+        use std::io::{Read, Write};
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        // There's nobody to create the file so this would block forever if it waited
+        let mut file = super::robust_wait_read_write_create(&file_path).unwrap();
+        file.write_all(b"satoshi nakamoto").unwrap();
+
+        // The existing file is opened without truncating it
+        let mut file = super::robust_wait_read_write_create(&file_path).unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "satoshi nakamoto");
+    }
+
+    #[test]
+    fn try_open_now() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let options = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone());
+        assert!(options.try_open_now(&file_path).unwrap().is_none());
+        std::fs::write(&file_path, "satoshi").unwrap();
+        assert!(options.try_open_now(&file_path).unwrap().is_some());
+        assert!(options.wait_for_min_size(16).try_open_now(&file_path).unwrap().is_none());
+    }
+
+    #[test]
+    fn open_when_created_with_mechanism() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        std::fs::write(&file_path, "satoshi nakamoto").unwrap();
+        let options = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone());
+
+        let (_, mechanism) = options.open_when_created_with_mechanism(&file_path).unwrap();
+        assert_eq!(mechanism, super::Mechanism::Inotify);
+        let (_, mechanism) = options.polling_only(std::time::Duration::from_millis(10)).open_when_created_with_mechanism(&file_path).unwrap();
+        assert_eq!(mechanism, super::Mechanism::Polling);
+    }
+
+    #[test]
+    fn retry_interrupted() {
+        let mut attempts = 0;
+        let result = super::retry_interrupted(|| {
+            attempts += 1;
+            match attempts {
+                1 | 2 => Err(std::io::Error::from(std::io::ErrorKind::Interrupted)),
+                _ => Ok(attempts),
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        let error = super::retry_interrupted(|| Err::<(), _>(std::io::Error::from(std::io::ErrorKind::NotFound))).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn default_polling_interval() {
+        assert_eq!(super::DEFAULT_POLLING_INTERVAL, std::time::Duration::from_secs(2));
+    }
+
+    #[test]
+    fn wait_error() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("missing").join("test");
+        let error = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .try_open_when_created(&file_path)
+            .unwrap_err();
+        match error {
+            super::WaitError::AddWatch(ref error) => assert_eq!(error.kind(), std::io::ErrorKind::NotFound),
+            ref error => panic!("unexpected error: {:?}", error),
+        }
+        assert_eq!(std::io::Error::from(error).kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn parent_removed() {
+        use super::ParentRemovedPolicy;
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let dir = temp_dir.join("dir");
+        let file_path = dir.join("test");
+        let options = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone());
+
+        std::fs::create_dir(&dir).unwrap();
+        let dir_thread = dir.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::remove_dir(&dir_thread).unwrap();
+        });
+        let error = options.clone()
+            .on_parent_removed(ParentRemovedPolicy::Error)
+            .try_open_when_created(&file_path)
+            .unwrap_err();
+        assert!(matches!(error, super::WaitError::ParentRemoved));
+        thread.join().unwrap();
+
+        std::fs::create_dir(&dir).unwrap();
+        let dir_thread = dir.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::remove_dir(&dir_thread).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::create_dir(&dir_thread).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(dir_thread.join("test"), "satoshi nakamoto").unwrap();
+        });
+        let file = options
+            .on_parent_removed(ParentRemovedPolicy::RecreateWatch)
+            .timeout(std::time::Duration::from_secs(10))
+            .open_when_created(&file_path)
+            .unwrap();
+        assert!(file.metadata().unwrap().is_file());
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn recreate_watch_on_ignored() {
+        fn churn(dir: std::path::PathBuf) -> std::thread::JoinHandle<()> {
+            std::thread::spawn(move || {
+                for _ in 0..2 {
+                    std::thread::sleep(std::time::Duration::from_millis(100));
+                    std::fs::remove_dir(&dir).unwrap();
+                    std::thread::sleep(std::time::Duration::from_millis(100));
+                    std::fs::create_dir(&dir).unwrap();
+                }
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                std::fs::write(dir.join("test"), "satoshi nakamoto").unwrap();
+            })
+        }
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let dir = temp_dir.join("dir");
+        let file_path = dir.join("test");
+        let options = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .timeout(std::time::Duration::from_secs(10));
+
+        std::fs::create_dir(&dir).unwrap();
+        let thread = churn(dir.clone());
+        let error = options.clone()
+            .recreate_watch_on_ignored(1)
+            .try_open_when_created(&file_path)
+            .unwrap_err();
+        assert!(matches!(error, super::WaitError::ParentRemoved));
+        thread.join().unwrap();
+
+        std::fs::remove_file(&file_path).unwrap();
+        let thread = churn(dir);
+        let file = options
+            .recreate_watch_on_ignored(2)
+            .open_when_created(&file_path)
+            .unwrap();
+        assert!(file.metadata().unwrap().is_file());
+        thread.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn max_fluke_retries() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let link_path = temp_dir.join("test.tmp");
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            // Opening a dangling symlink fails with `NotFound` which looks like a fluke
+            std::os::unix::fs::symlink("nonexistent", &link_path).unwrap();
+            std::fs::rename(&link_path, &file_path_thread).unwrap();
+        });
+        let error = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .retry_on_fluke(true)
+            .max_fluke_retries(0)
+            .try_open_when_created(&file_path)
+            .unwrap_err();
+        match error {
+            super::WaitError::FlukeRetriesExhausted(_) => (),
+            error => panic!("unexpected error: {:?}", error),
+        }
+        thread.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn fluke_cooldown() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let file_path_thread = file_path.clone();
+        let link_path = temp_dir.join("link");
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            for _ in 0..5 {
+                std::os::unix::fs::symlink("nonexistent", &link_path).unwrap();
+                std::fs::rename(&link_path, &file_path_thread).unwrap();
+                std::fs::remove_file(&file_path_thread).unwrap();
+            }
+            std::fs::write(&file_path_thread, "satoshi nakamoto").unwrap();
+        });
+        let file = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .retry_on_fluke(true)
+            .max_fluke_retries(5)
+            .fluke_cooldown(std::time::Duration::from_millis(50))
+            .open_when_created(&file_path)
+            .unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 16);
+        thread.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn open_when_created_with_metadata() {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let old_path = temp_dir.join("old");
+        std::fs::write(&old_path, "old").unwrap();
+        let old = std::fs::File::open(&old_path).unwrap();
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            let new_path = file_path_thread.with_extension("new");
+            std::fs::write(&new_path, "satoshi nakamoto").unwrap();
+            std::fs::rename(&new_path, &file_path_thread).unwrap();
+        });
+        let (_, metadata) = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .open_when_created_with_metadata(&file_path)
+            .unwrap();
+        assert_eq!(metadata.len(), 16);
+        assert_ne!(metadata.ino(), old.metadata().unwrap().ino());
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn wait_read_to_vec_sized() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(&file_path_thread, "satoshi nakamoto").unwrap();
+        });
+        let contents = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .wait_for_min_size(16)
+            .wait_read_to_vec_sized(&file_path)
+            .unwrap();
+        assert_eq!(contents, b"satoshi nakamoto");
+        assert!(contents.capacity() >= 16);
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn wait_until_created_stat() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(&file_path_thread, "satoshi nakamoto").unwrap();
+        });
+        let metadata = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .wait_until_created_stat(&file_path)
+            .unwrap();
+        thread.join().unwrap();
+        assert_eq!(metadata.len(), 16);
+        assert_eq!(metadata.modified().unwrap(), std::fs::metadata(&file_path).unwrap().modified().unwrap());
+    }
+
+    #[test]
+    #[cfg(not(any(target_os = "macos", target_os = "freebsd", target_os = "netbsd", target_os = "openbsd", target_os = "dragonfly")))]
+    fn only_accept_rename() {
+        use std::io::Read;
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(&file_path_thread, "direct").unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            let temp_path = file_path_thread.with_extension("tmp");
+            std::fs::write(&temp_path, "renamed").unwrap();
+            std::fs::rename(&temp_path, &file_path_thread).unwrap();
+        });
+        let options = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .only_accept_rename(true);
+        let mut file = options.open_when_created(&file_path).unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "renamed");
+        thread.join().unwrap();
+
+        let error = options
+            .polling_only(std::time::Duration::from_millis(10))
+            .open_when_created(temp_dir.join("missing"))
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn retry_on_error() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        std::fs::write(&file_path, "satoshi nakamoto").unwrap();
+        let attempts = std::sync::Arc::new(AtomicUsize::new(0));
+        let attempts_opener = attempts.clone();
+        let options = super::Options::with_open_options(std::fs::OpenOptions::new())
+            .polling_only(std::time::Duration::from_millis(10))
+            .with_opener(move |path| match attempts_opener.fetch_add(1, Ordering::Relaxed) {
+                0 | 1 => Err(std::io::Error::other("busy")),
+                _ => std::fs::File::open(path),
+            });
+        // Not retried by default
+        assert_eq!(options.open_when_created(&file_path).unwrap_err().kind(), std::io::ErrorKind::Other);
+        attempts.store(0, Ordering::Relaxed);
+        let file = options
+            .retry_on_error(|error| error.kind() == std::io::ErrorKind::Other)
+            .open_when_created(&file_path)
+            .unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 16);
+        assert_eq!(attempts.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn name_too_long() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("a".repeat(300));
+        let error = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .try_open_when_created(&file_path)
+            .unwrap_err();
+        match error {
+            super::WaitError::Open(error) => assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput),
+            error => panic!("unexpected error: {:?}", error),
+        }
+    }
+
+    #[test]
+    fn with_opener() {
+        use std::io::Read;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let backing = temp_dir.join("backing");
+        std::fs::write(&backing, "satoshi nakamoto").unwrap();
+        let attempts = std::sync::Arc::new(AtomicUsize::new(0));
+        let attempts_opener = attempts.clone();
+        // The path itself never exists
+        let mut file = super::Options::with_open_options(std::fs::OpenOptions::new())
+            .polling_only(std::time::Duration::from_millis(10))
+            .with_opener(move |path| {
+                assert_eq!(path, std::path::Path::new("/nonexistent/test"));
+                match attempts_opener.fetch_add(1, Ordering::Relaxed) {
+                    0 | 1 => Err(std::io::ErrorKind::NotFound.into()),
+                    _ => std::fs::File::open(&backing),
+                }
+            })
+            .open_when_created("/nonexistent/test")
+            .unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "satoshi nakamoto");
+        assert_eq!(attempts.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn wait_for_nonempty() {
         use std::io::{Read, Write};
 
-        let test_string = "satoshi nakamoto";
         let temp_dir = mktemp::Temp::new_dir().unwrap();
         let file_path = temp_dir.join("test");
         let file_path_thread = file_path.clone();
         let thread = std::thread::spawn(move || {
-            // Sleeps between operations should make it easier to detect inconsistencies
-            std::thread::sleep(std::time::Duration::from_secs(2));
+            std::thread::sleep(std::time::Duration::from_millis(100));
             let mut file = std::fs::File::create(&file_path_thread).unwrap();
-            std::thread::sleep(std::time::Duration::from_secs(1));
-            file.write_all(test_string.as_bytes()).unwrap();
-            std::thread::sleep(std::time::Duration::from_secs(1));
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            file.write_all(b"satoshi nakamoto").unwrap();
         });
-        let mut file = super::robust_wait_read(&file_path).unwrap();
+        let mut file = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .assume_create_is_atomic(true)
+            .wait_for_nonempty(true)
+            .open_when_created(&file_path)
+            .unwrap();
         let mut contents = String::new();
         file.read_to_string(&mut contents).unwrap();
-        assert_eq!(contents, test_string);
+        assert_eq!(contents, "satoshi nakamoto");
+        thread.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn allow_empty_file() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let options = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .wait_for_nonempty(true)
+            .timeout(std::time::Duration::from_millis(500));
+
+        for allow in [false, true] {
+            let _ = std::fs::remove_file(&file_path);
+            let file_path_thread = file_path.clone();
+            let thread = std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                std::fs::File::create(&file_path_thread).unwrap();
+            });
+            let result = options.clone().allow_empty_file(allow).open_when_created(&file_path);
+            thread.join().unwrap();
+            if allow {
+                assert_eq!(result.unwrap().metadata().unwrap().len(), 0);
+            } else {
+                assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn follow_symlinks() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let target = temp_dir.join("target");
+        let file_path = temp_dir.join("test");
+        std::fs::write(&target, "satoshi nakamoto").unwrap();
+        std::os::unix::fs::symlink(&target, &file_path).unwrap();
+        let options = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone());
+        options.clone().open_when_created(&file_path).unwrap();
+        let error = options.clone().follow_symlinks(false).open_when_created(&file_path).unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(libc::ELOOP));
+        let error = options.follow_symlinks(false).wait_only(&file_path).unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(libc::ELOOP));
+    }
+
+    #[test]
+    fn wait_for_parent() {
+        use std::io::Read;
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("a").join("b").join("test");
+        let dir_thread = temp_dir.to_path_buf();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::create_dir(dir_thread.join("a")).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::create_dir(dir_thread.join("a").join("b")).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(dir_thread.join("a").join("b").join("test"), "satoshi nakamoto").unwrap();
+        });
+        let mut file = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .wait_for_parent(true)
+            .timeout(std::time::Duration::from_secs(5))
+            .open_when_created(&file_path)
+            .unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "satoshi nakamoto");
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn watch_dir_override() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let opened_dir = temp_dir.join("opened");
+        let watched_dir = temp_dir.join("watched");
+        std::fs::create_dir(&opened_dir).unwrap();
+        std::fs::create_dir(&watched_dir).unwrap();
+        let created = Arc::new(AtomicBool::new(false));
+        let created_thread = created.clone();
+        let opened_dir_thread = opened_dir.clone();
+        let watched_dir_thread = watched_dir.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            // Not watched so the wait continues
+            std::fs::write(opened_dir_thread.join("test"), "satoshi nakamoto").unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            created_thread.store(true, Ordering::SeqCst);
+            std::fs::write(watched_dir_thread.join("test"), "").unwrap();
+        });
+        let options = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .watch_dir_override(watched_dir.clone());
+        let watcher = options.watcher(opened_dir.join("test")).unwrap();
+        assert_eq!(watcher.watched_dir(), watched_dir);
+        drop(watcher);
+        let contents = std::io::read_to_string(options.open_when_created(opened_dir.join("test")).unwrap()).unwrap();
+        assert!(created.load(Ordering::SeqCst));
+        assert_eq!(contents, "satoshi nakamoto");
+        thread.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn canonicalize_parent() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        std::fs::create_dir_all(temp_dir.join("real").join("nested")).unwrap();
+        std::fs::create_dir(temp_dir.join("b")).unwrap();
+        std::os::unix::fs::symlink(temp_dir.join("real").join("nested"), temp_dir.join("link")).unwrap();
+        // `..` of the symlink is `real` rather than the temporary directory
+        let file_path = temp_dir.join("link").join("..").join("b").join("test");
+        std::fs::create_dir(temp_dir.join("real").join("b")).unwrap();
+        std::fs::write(temp_dir.join("real").join("b").join("test"), "satoshi nakamoto").unwrap();
+        let options = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .canonicalize_parent(true);
+        let watcher = options.watcher(&file_path).unwrap();
+        assert_eq!(watcher.watched_dir(), temp_dir.join("real").join("b").canonicalize().unwrap());
+        drop(watcher);
+        let contents = std::io::read_to_string(options.open_when_created(&file_path).unwrap()).unwrap();
+        assert_eq!(contents, "satoshi nakamoto");
+
+        // Fails right away instead of waiting for the parent
+        let missing = temp_dir.join("missing").join("test");
+        let error = options.wait_for_parent(true).timeout(std::time::Duration::from_secs(5)).try_open_when_created(missing).unwrap_err();
+        assert!(matches!(error, super::WaitError::AddWatch(_)));
+    }
+
+    #[test]
+    fn inotify_with_polling() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let source = temp_dir.join("source");
+        let file_path = temp_dir.join("test");
+        std::fs::write(&source, "satoshi nakamoto").unwrap();
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            // Linking only produces IN_CREATE which is not watched by default
+            std::fs::hard_link(&source, &file_path_thread).unwrap();
+        });
+        super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .inotify_with_polling(std::time::Duration::from_millis(50))
+            .timeout(std::time::Duration::from_secs(5))
+            .open_when_created(&file_path)
+            .unwrap();
+        thread.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn create_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let file = super::Options::with_open_options(std::fs::OpenOptions::new().write(true).create(true).clone())
+            .create_mode(0o600)
+            .open_when_created(&file_path)
+            .unwrap();
+        assert_eq!(file.metadata().unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn retry_on_permission_denied() {
+        use std::io::Read;
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            use std::io::Write;
+
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            let mut file = std::fs::OpenOptions::new().write(true).create_new(true).mode(0o200).open(&file_path_thread).unwrap();
+            file.write_all(b"satoshi nakamoto").unwrap();
+            drop(file);
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            std::fs::set_permissions(&file_path_thread, std::fs::Permissions::from_mode(0o600)).unwrap();
+        });
+        // Root is not affected by permissions so this only checks that nothing breaks then
+        let mut file = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .retry_on_permission_denied(true)
+            .open_when_created(&file_path)
+            .unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "satoshi nakamoto");
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn wait_for_min_size() {
+        use std::io::{Read, Write};
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            let mut file = std::fs::File::create(&file_path_thread).unwrap();
+            file.write_all(b"satoshi").unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            file.write_all(b" nakamoto").unwrap();
+        });
+        let options = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .assume_create_is_atomic(true)
+            .wait_for_min_size(16);
+        let mut file = options.open_when_created(&file_path).unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "satoshi nakamoto");
+        thread.join().unwrap();
+
+        // The file is returned if the writer closes it before reaching the size
+        let file_path = temp_dir.join("short");
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(&file_path_thread, "short").unwrap();
+        });
+        let (mut file, kind) = options.assume_create_is_atomic(false).open_when_created_with_event(&file_path).unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "short");
+        assert_eq!(kind, super::CreationKind::ClosedWrite);
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn wait_for_prefix() {
+        use std::io::{Read, Seek, Write};
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test.png");
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            let mut file = std::fs::File::create(&file_path_thread).unwrap();
+            file.write_all(b"\x89P").unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            file.write_all(b"NG\r\n").unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(200));
+        });
+        let mut file = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .wait_for_prefix(b"\x89PNG")
+            .open_when_created(&file_path)
+            .unwrap();
+        assert_eq!(file.stream_position().unwrap(), 0);
+        let mut start = [0; 4];
+        file.read_exact(&mut start).unwrap();
+        assert_eq!(&start, b"\x89PNG");
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn wait_for_quiescence() {
+        use std::io::{Read, Write};
+
+        let options = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .assume_create_is_atomic(true)
+            .wait_for_quiescence(std::time::Duration::from_millis(300));
+        let polling = options.clone().polling_only(std::time::Duration::from_millis(10));
+
+        for options in [options, polling] {
+            let temp_dir = mktemp::Temp::new_dir().unwrap();
+            let file_path = temp_dir.join("test");
+            let file_path_thread = file_path.clone();
+            let thread = std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                let mut file = std::fs::File::create(&file_path_thread).unwrap();
+                for part in ["satoshi", " ", "nakamoto"] {
+                    std::thread::sleep(std::time::Duration::from_millis(100));
+                    file.write_all(part.as_bytes()).unwrap();
+                }
+            });
+            let mut file = options.open_when_created(&file_path).unwrap();
+            let mut contents = String::new();
+            file.read_to_string(&mut contents).unwrap();
+            assert_eq!(contents, "satoshi nakamoto");
+            thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn wait_dir_created() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let dir_path = temp_dir.join("test");
+        let dir_path_thread = dir_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::create_dir(&dir_path_thread).unwrap();
+        });
+        super::wait_dir_created(&dir_path).unwrap();
+        assert!(dir_path.is_dir());
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn expect_directory_rejects_file() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        std::fs::write(&file_path, "satoshi nakamoto").unwrap();
+        let error = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .expect_directory(true)
+            .open_when_created(&file_path)
+            .unwrap_err();
+        assert_eq!(error.raw_os_error(), super::not_directory_error().raw_os_error());
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn with_inotify() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let other_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let file_path_thread = file_path.clone();
+        let mut inotify = inotify::Inotify::init().unwrap();
+        let other_watch = inotify.add_watch(&*other_dir, inotify::WatchMask::CREATE).unwrap();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(&file_path_thread, "satoshi nakamoto").unwrap();
+        });
+        super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .open_when_created_with_inotify(&mut inotify, &file_path)
+            .unwrap();
+        thread.join().unwrap();
+        // The other watch is still present
+        inotify.rm_watch(other_watch).unwrap();
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn in_watched() {
+        use inotify::WatchMask;
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let other_dir = mktemp::Temp::new_dir().unwrap();
+        let mut inotify = inotify::Inotify::init().unwrap();
+        let mask = WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::DELETE_SELF;
+        let watch = inotify.add_watch(&*temp_dir, mask).unwrap();
+        inotify.add_watch(&*other_dir, mask).unwrap();
+        let temp_dir_thread = temp_dir.to_path_buf();
+        let other_dir_thread = other_dir.to_path_buf();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            // The same name in a different watched directory is ignored
+            std::fs::write(other_dir_thread.join("test"), "other").unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(temp_dir_thread.join("test"), "satoshi nakamoto").unwrap();
+        });
+        let options = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone());
+        let file = options.open_when_created_in_watched(&mut inotify, &watch, &temp_dir, "test".as_ref()).unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 16);
+        thread.join().unwrap();
+
+        let error = options.open_when_created_in_watched(&mut inotify, &watch, &temp_dir, "a/b".as_ref()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        // The watch is still present
+        inotify.rm_watch(watch).unwrap();
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn open_at() {
+        use std::io::Read;
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let original = temp_dir.join("original");
+        let renamed = temp_dir.join("renamed");
+        std::fs::create_dir(&original).unwrap();
+        let dir = std::fs::File::open(&original).unwrap();
+        std::fs::rename(&original, &renamed).unwrap();
+        let file_path_thread = renamed.join("test");
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(&file_path_thread, "satoshi nakamoto").unwrap();
+        });
+        let options = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone());
+        let mut file = options.open_when_created_at(&dir, "test".as_ref()).unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "satoshi nakamoto");
+        thread.join().unwrap();
+
+        let error = options.open_when_created_at(&dir, "../test".as_ref()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+
+        // Polling uses the descriptor as well
+        let dir_thread = renamed.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            // Polling could find the file before it's written
+            std::fs::write(dir_thread.join("polled.tmp"), "polled").unwrap();
+            std::fs::rename(dir_thread.join("polled.tmp"), dir_thread.join("polled")).unwrap();
+        });
+        let mut file = options.polling_only(std::time::Duration::from_millis(10)).open_when_created_at(&dir, "polled".as_ref()).unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "polled");
+        thread.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn on_event() {
+        use std::sync::{Arc, Mutex};
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let other_path = temp_dir.join("other");
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(&other_path, "other").unwrap();
+            std::fs::write(&file_path_thread, "satoshi nakamoto").unwrap();
+        });
+        let names = Arc::new(Mutex::new(Vec::new()));
+        let names_callback = Arc::clone(&names);
+        super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .on_event(move |event| names_callback.lock().unwrap().extend(event.name.map(ToOwned::to_owned)))
+            .open_when_created(&file_path)
+            .unwrap();
+        thread.join().unwrap();
+        let names = names.lock().unwrap();
+        assert!(names.iter().any(|name| name == "other"));
+        assert!(names.iter().any(|name| name == "test"));
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn extra_watch_mask() {
+        use inotify::WatchMask;
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(&file_path_thread, "satoshi nakamoto").unwrap();
+        });
+        let (_, kind) = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .extra_watch_mask(WatchMask::DONT_FOLLOW | WatchMask::EXCL_UNLINK | WatchMask::OPEN)
+            .open_when_created_with_event(&file_path)
+            .unwrap();
+        assert_eq!(kind, super::CreationKind::ClosedWrite);
+        thread.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn expect_file_type() {
+        use super::FileType;
+        use std::os::unix::ffi::OsStrExt;
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let c_path = std::ffi::CString::new(file_path.as_os_str().as_bytes()).unwrap();
+        // SAFETY: the path is a valid C string
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+
+        let options = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone());
+        // Would block forever without O_NONBLOCK since there's no writer
+        let file = options.clone().expect_file_type(FileType::Fifo).open_when_created(&file_path).unwrap();
+        assert!(std::os::unix::fs::FileTypeExt::is_fifo(&file.metadata().unwrap().file_type()));
+        let error = options
+            .expect_file_type(FileType::Regular)
+            .timeout(std::time::Duration::from_millis(100))
+            .open_when_created(&file_path)
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn open_when_created_cstr() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        // Not valid UTF-8
+        let file_path = temp_dir.join(std::ffi::OsStr::from_bytes(b"test\xff"));
+        let c_path = std::ffi::CString::new(file_path.as_os_str().as_bytes()).unwrap();
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(&file_path_thread, "satoshi nakamoto").unwrap();
+        });
+        let file = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .open_when_created_cstr(&c_path)
+            .unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 16);
+        thread.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn custom_flags() {
+        use std::os::unix::fs::OpenOptionsExt;
+        use std::os::unix::io::AsRawFd;
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(&file_path_thread, "satoshi nakamoto").unwrap();
+        });
+        let file = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK).clone())
+            .open_when_created(&file_path)
+            .unwrap();
+        thread.join().unwrap();
+        // SAFETY: F_GETFL doesn't touch memory and the descriptor is valid
+        let flags = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETFL) };
+        assert_ne!(flags & libc::O_NONBLOCK, 0);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn owned_fd() {
+        use std::io::Read;
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        std::fs::write(&file_path, "satoshi nakamoto").unwrap();
+        let fd = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .open_when_created_owned_fd(&file_path)
+            .unwrap();
+        let mut contents = String::new();
+        std::fs::File::from(fd).read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "satoshi nakamoto");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn polling_timed_out() {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        // Root is not affected by permissions so the file would be opened
+        // SAFETY: geteuid has no preconditions
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        std::fs::OpenOptions::new().write(true).create_new(true).mode(0o000).open(&file_path).unwrap();
+        let error = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .retry_on_permission_denied(true)
+            .polling_only(std::time::Duration::from_millis(10))
+            .timeout(std::time::Duration::from_millis(50))
+            .try_open_when_created(&file_path)
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+        match error {
+            super::WaitError::PollingTimedOut { watch, last_open: Some(last_open) } => {
+                assert!(matches!(*watch, super::WaitError::InotifyInit(_)));
+                assert_eq!(last_open.kind(), std::io::ErrorKind::PermissionDenied);
+            },
+            error => panic!("unexpected error: {:?}", error),
+        }
+    }
+
+    #[test]
+    fn polling_only() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(&file_path_thread, "satoshi nakamoto").unwrap();
+        });
+        let (_, kind) = super::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .polling_only(std::time::Duration::from_millis(20))
+            .open_when_created_with_event(&file_path)
+            .unwrap();
+        assert_eq!(kind, super::CreationKind::NoEvent);
         thread.join().unwrap();
     }
 }
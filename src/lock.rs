@@ -0,0 +1,94 @@
+//! Locking the file as part of opening it.
+
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use crate::{CreationKind, Options, retry_interrupted};
+
+/// Kind of the advisory lock acquired by `Options::open_when_created_locked`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LockType {
+    /// Shared lock, multiple processes may hold it at the same time.
+    Shared,
+    /// Exclusive lock, only one process may hold it.
+    Exclusive,
+}
+
+impl Options {
+    /// Opens the file once it's available and acquires an advisory lock on it.
+    ///
+    /// The lock is acquired using `flock(2)` so it's only respected by processes using `flock`
+    /// as well. The file is returned only once the lock is held. If the file at `path` was
+    /// replaced or deleted while waiting for the lock it's not returned and waiting continues for
+    /// the new file. This avoids races with other processes modifying the file between opening and
+    /// locking it.
+    ///
+    /// Note that waiting for the lock itself is not limited by the timeout. The lock is released
+    /// when the file is closed.
+    pub fn open_when_created_locked<P: AsRef<Path>>(&self, path: P, lock_type: LockType) -> io::Result<File> {
+        self.internal_wait(path.as_ref(), None, &|path, kind| self.attempt_open_locked(path, kind, lock_type))
+            .map(|(file, _)| file)
+            .map_err(Into::into)
+    }
+
+    fn attempt_open_locked(&self, path: &Path, kind: CreationKind, lock_type: LockType) -> io::Result<Option<File>> {
+        let file = match self.attempt_open(path, kind)? {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+
+        let operation = match lock_type {
+            LockType::Shared => libc::LOCK_SH,
+            LockType::Exclusive => libc::LOCK_EX,
+        };
+        retry_interrupted(|| {
+            // SAFETY: flock doesn't touch memory and the descriptor is valid
+            match unsafe { libc::flock(file.as_raw_fd(), operation) } {
+                0 => Ok(()),
+                _ => Err(io::Error::last_os_error()),
+            }
+        })?;
+
+        // The path may refer to a different file by now
+        let locked = file.metadata()?;
+        let current = if self.follow_symlinks {
+            std::fs::metadata(path)
+        } else {
+            std::fs::symlink_metadata(path)
+        };
+        match current {
+            Ok(current) if current.dev() == locked.dev() && current.ino() == locked.ino() => Ok(Some(file)),
+            Ok(_) => Ok(None),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn open_when_created_locked() {
+        use std::os::unix::io::AsRawFd;
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(&file_path_thread, "satoshi nakamoto").unwrap();
+        });
+        let options = crate::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone());
+        let file = options.open_when_created_locked(&file_path, super::LockType::Exclusive).unwrap();
+        thread.join().unwrap();
+
+        let other = std::fs::File::open(&file_path).unwrap();
+        // SAFETY: flock doesn't touch memory and the descriptor is valid
+        assert_eq!(unsafe { libc::flock(other.as_raw_fd(), libc::LOCK_SH | libc::LOCK_NB) }, -1);
+        drop(file);
+        // SAFETY: same as above
+        assert_eq!(unsafe { libc::flock(other.as_raw_fd(), libc::LOCK_SH | libc::LOCK_NB) }, 0);
+    }
+}
@@ -0,0 +1,429 @@
+//! Waiting for multiple files at once.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::{Attempt, Change, ChangeSource, CreationKind, EventBuffer, Notification, OpenResult, Options, WaitContext, WaitError, WaitState, Wakeup, backend, check_notified, inotify_disabled, next_poll, retry_interrupted, split_path};
+
+impl Options {
+    /// Opens whichever of the files becomes available first.
+    ///
+    /// All parent directories are watched using a single watcher and each directory is watched
+    /// only once even if multiple paths are located in it. The paths are checked in the order
+    /// they were given so if multiple files are available the first one is returned along with
+    /// its path.
+    ///
+    /// Returns an error with `ErrorKind::InvalidInput` if `paths` is empty.
+    pub fn open_any_when_created(&self, paths: &[&Path]) -> io::Result<(PathBuf, File)> {
+        if paths.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no paths to wait for were given"));
+        }
+
+        let context = self.wait_context(None);
+
+        if self.polling_only {
+            return self.try_fallback_open_any(paths, inotify_disabled(), &context);
+        }
+
+        match self.watch_targets(paths) {
+            Ok((watcher, watches, targets)) => self.wait_for_any(watcher, &watches, &targets, &context),
+            Err(error) => self.try_fallback_open_any(paths, error, &context),
+        }
+    }
+
+    /// Opens all the files once they are available.
+    ///
+    /// The directories are watched the same way as in `open_any_when_created`. Files which
+    /// already exist are opened right away and the remaining ones are opened as they appear.
+    /// The files are returned in the same order as `paths` regardless of the order in which they
+    /// were created, i.e. the file at index `i` is the one at `paths[i]`.
+    pub fn open_all_when_created(&self, paths: &[&Path]) -> io::Result<Vec<File>> {
+        self.internal_wait_all(paths, &|path, kind| self.attempt_open(path, kind))
+    }
+
+    /// Waits until all the files are available and opens them only when iterated.
+    ///
+    /// This behaves the same as `open_all_when_created` but the files are closed right after
+    /// checking that they are ready so at most one of them is open while waiting. This avoids
+    /// exhausting the limit of file descriptors when waiting for many files. Each file is opened
+    /// again when the returned iterator reaches it so it can be dropped before opening the next
+    /// one.
+    ///
+    /// Note that a file may be deleted or replaced between the check and opening it in which case
+    /// the iterator yields the error or the replacement. The readiness criteria such as
+    /// `wait_for_min_size` are not checked again.
+    pub fn open_all_when_created_lazy<'a>(&'a self, paths: &'a [&'a Path]) -> io::Result<impl Iterator<Item = (PathBuf, io::Result<File>)> + 'a> {
+        self.wait_all_ready(paths)?;
+        Ok(paths.iter().map(move |path| (path.to_path_buf(), self.open_ready(path))))
+    }
+
+    /// Waits until all the files are ready closing each of them right after checking it.
+    pub(crate) fn wait_all_ready(&self, paths: &[&Path]) -> io::Result<()> {
+        self.internal_wait_all(paths, &|path, kind| self.attempt_open(path, kind).map(|file| file.map(drop))).map(drop)
+    }
+
+    /// Opens the file which was already found to be ready.
+    pub(crate) fn open_ready(&self, path: &Path) -> io::Result<File> {
+        retry_interrupted(|| self.open_path(path))
+    }
+
+    fn internal_wait_all<T>(&self, paths: &[&Path], attempt: &Attempt<'_, T>) -> io::Result<Vec<T>> {
+        let context = self.wait_context(None);
+        let mut values = paths.iter().map(|_| None).collect::<Vec<_>>();
+
+        if self.polling_only {
+            self.try_fallback_open_all(paths, &mut values, inotify_disabled(), &context, attempt)?;
+        } else {
+            match self.watch_targets(paths) {
+                Ok((watcher, watches, targets)) => self.wait_for_all(watcher, &watches, &targets, &mut values, &context, attempt)?,
+                Err(error) => self.try_fallback_open_all(paths, &mut values, error, &context, attempt)?,
+            }
+        }
+
+        Ok(values.into_iter().map(|value| value.expect("all files were opened")).collect())
+    }
+
+    /// Watches the parent directories of all paths, each directory only once.
+    ///
+    /// Returns the indices of the targets in each watched directory. Returns an error if the
+    /// watch could not be set up and polling should be used.
+    // The watch ID is `Copy` only on some platforms
+    #[allow(clippy::clone_on_copy)]
+    fn watch_targets<'a>(&self, paths: &[&'a Path]) -> io::Result<(backend::DirWatcher, Watches, Vec<Target<'a>>)> {
+        let mut watcher = backend::DirWatcher::new(self).map_err(WaitError::inotify_init)?;
+
+        let mut dirs = HashMap::<&Path, backend::WatchId>::new();
+        let mut watches = Watches::new();
+        let mut targets = Vec::with_capacity(paths.len());
+        for (index, path) in paths.iter().enumerate() {
+            let (dir, file_name) = split_path(path)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "the path doesn't contain a file name"))?;
+
+            let watch = match dirs.get(dir) {
+                Some(watch) => watch.clone(),
+                None => {
+                    let watch = watcher.watch(dir).map_err(WaitError::add_watch)?;
+                    dirs.insert(dir, watch.clone());
+                    watch
+                },
+            };
+            watches.entry(watch).or_default().push(index);
+
+            targets.push(Target { path, file_name, });
+        }
+
+        Ok((watcher, watches, targets))
+    }
+
+    fn try_fallback_open_any(&self, paths: &[&Path], inotify_error: io::Error, context: &WaitContext<'_>) -> io::Result<(PathBuf, File)> {
+        if self.only_accept_rename {
+            return Err(inotify_error);
+        }
+        let mut state = WaitState::polling(self.wait_config());
+        loop {
+            let mut round = OpenResult::NotFound;
+            for path in paths {
+                match self.attempt_open(path, CreationKind::NoEvent) {
+                    Ok(Some(file)) => {
+                        self.wait_unchanged(path, context)?;
+                        return Ok((path.to_path_buf(), file));
+                    },
+                    Ok(None) => round = OpenResult::NotReady,
+                    Err(error) if error.kind() == io::ErrorKind::NotFound => (),
+                    Err(error) => return Err(error),
+                }
+            }
+
+            if !next_poll(&mut state, round, context)? {
+                return Err(inotify_error);
+            }
+        }
+    }
+
+    fn try_fallback_open_all<T>(&self, paths: &[&Path], files: &mut [Option<T>], inotify_error: io::Error, context: &WaitContext<'_>, attempt: &Attempt<'_, T>) -> io::Result<()> {
+        if self.only_accept_rename {
+            return Err(inotify_error);
+        }
+        let mut state = WaitState::polling(self.wait_config());
+        loop {
+            let mut round = OpenResult::NotFound;
+            for (path, file) in paths.iter().zip(files.iter_mut()) {
+                if file.is_some() {
+                    continue;
+                }
+
+                match attempt(path, CreationKind::NoEvent) {
+                    Ok(Some(opened)) => {
+                        self.wait_unchanged(path, context)?;
+                        *file = Some(opened);
+                    },
+                    Ok(None) => round = OpenResult::NotReady,
+                    Err(error) if error.kind() == io::ErrorKind::NotFound => (),
+                    Err(error) => return Err(error),
+                }
+            }
+
+            if files.iter().all(Option::is_some) {
+                return Ok(());
+            }
+            if !next_poll(&mut state, round, context)? {
+                return Err(inotify_error);
+            }
+        }
+    }
+
+    fn wait_for_any(&self, mut watcher: backend::DirWatcher, watches: &Watches, targets: &[Target<'_>], context: &WaitContext<'_>) -> io::Result<(PathBuf, File)> {
+        let mut buffer = EventBuffer::new(self);
+        // All files have to be checked initially
+        let mut checks = vec![Check::NotFoundIsOk; targets.len()];
+        let mut state = WaitState::watching(self.wait_config());
+        let attempt = |path: &Path, kind| self.attempt_open(path, kind);
+
+        loop {
+            for (target, check) in targets.iter().zip(&checks) {
+                if *check == Check::Skip {
+                    continue;
+                }
+
+                if let Some(file) = self.check_target(target, *check, &mut state, context, &attempt)? {
+                    return Ok((target.path.to_path_buf(), file));
+                }
+            }
+
+            match self.wait_checks(&mut watcher, &mut buffer, watches, targets, &mut checks, context) {
+                Ok(()) => (),
+                Err(WaitError::ReadEvents(error)) => {
+                    let paths = targets.iter().map(|target| target.path).collect::<Vec<_>>();
+                    return self.try_fallback_open_any(&paths, error, context);
+                },
+                Err(error) => return Err(error.into()),
+            }
+        }
+    }
+
+    fn wait_for_all<T>(&self, mut watcher: backend::DirWatcher, watches: &Watches, targets: &[Target<'_>], files: &mut [Option<T>], context: &WaitContext<'_>, attempt: &Attempt<'_, T>) -> io::Result<()> {
+        let mut buffer = EventBuffer::new(self);
+        // All files have to be checked initially so that the existing ones are not waited for
+        let mut checks = vec![Check::NotFoundIsOk; targets.len()];
+        let mut state = WaitState::watching(self.wait_config());
+
+        loop {
+            for ((target, check), file) in targets.iter().zip(&checks).zip(files.iter_mut()) {
+                if *check == Check::Skip || file.is_some() {
+                    continue;
+                }
+
+                *file = self.check_target(target, *check, &mut state, context, attempt)?;
+            }
+
+            if files.iter().all(Option::is_some) {
+                return Ok(());
+            }
+            match self.wait_checks(&mut watcher, &mut buffer, watches, targets, &mut checks, context) {
+                Ok(()) => (),
+                Err(WaitError::ReadEvents(error)) => {
+                    let paths = targets.iter().map(|target| target.path).collect::<Vec<_>>();
+                    return self.try_fallback_open_all(&paths, files, error, context, attempt);
+                },
+                Err(error) => return Err(error.into()),
+            }
+        }
+    }
+
+    /// Attempts to open the target after it might have changed.
+    fn check_target<T>(&self, target: &Target<'_>, check: Check, state: &mut WaitState, context: &WaitContext<'_>, attempt: &Attempt<'_, T>) -> io::Result<Option<T>> {
+        let notification = match check {
+            Check::MustExist(kind) => Notification::Available(kind),
+            _ => Notification::Recheck,
+        };
+        let file = check_notified(state, notification, context, |kind| attempt(target.path, kind))?;
+        if file.is_some() {
+            self.wait_unchanged(target.path, context)?;
+        }
+        Ok(file)
+    }
+
+    /// Waits until some of the targets have to be checked again and marks them in `checks`.
+    ///
+    /// Returns `WaitError::ReadEvents` if the watch failed and polling should take over.
+    fn wait_checks(&self, watcher: &mut backend::DirWatcher, buffer: &mut [u8], watches: &Watches, targets: &[Target<'_>], checks: &mut [Check], context: &WaitContext<'_>) -> Result<(), WaitError> {
+        checks.iter_mut().for_each(|check| *check = Check::Skip);
+        while checks.iter().all(|check| *check == Check::Skip) {
+            match context.limited(self.recheck_interval).wait_readable(&*watcher) {
+                Ok(Wakeup::Readable) => (),
+                // Time to re-check in case events were lost
+                Ok(Wakeup::TimedOut) if !context.is_expired() => {
+                    checks.iter_mut().for_each(|check| *check = Check::NotFoundIsOk);
+                    continue;
+                },
+                Ok(Wakeup::TimedOut) => return Err(context.timed_out()),
+                Ok(Wakeup::Cancelled) => return Err(WaitError::Cancelled),
+                Err(error) => return Err(WaitError::ReadEvents(error)),
+            }
+
+            let mut removed = false;
+            let result = watcher.read_changes(buffer, |watch, change| {
+                // Overflow concerns all watches
+                if let Change::Overflow = change {
+                    checks.iter_mut()
+                        .filter(|check| **check == Check::Skip)
+                        .for_each(|check| *check = Check::NotFoundIsOk);
+                    return;
+                }
+
+                for &index in watches.get(watch).into_iter().flatten() {
+                    let check = &mut checks[index];
+                    match change {
+                        Change::Entry(name, kind) if name == targets[index].file_name => {
+                            *check = Check::MustExist(kind);
+                        },
+                        Change::Entry(_, _) => (),
+                        Change::Unknown => if *check == Check::Skip {
+                            *check = Check::NotFoundIsOk;
+                        },
+                        Change::Removed => removed = true,
+                        Change::Overflow => (),
+                    }
+                }
+            });
+
+            match result {
+                Ok(()) if removed => return Err(WaitError::ReadEvents(io::Error::from(io::ErrorKind::NotFound))),
+                Ok(()) => (),
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => (),
+                Err(error) => return Err(WaitError::ReadEvents(error)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Indices of the targets located in each watched directory.
+type Watches = HashMap<backend::WatchId, Vec<usize>>;
+
+struct Target<'a> {
+    path: &'a Path,
+    file_name: &'a OsStr,
+}
+
+/// How the file should be checked after receiving changes.
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Check {
+    /// There was no change concerning the file.
+    Skip,
+    /// The file might have been created.
+    NotFoundIsOk,
+    /// The file was reported to be created so it not existing is a fluke.
+    MustExist(CreationKind),
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn open_any() {
+        use std::io::Read;
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let other_dir = mktemp::Temp::new_dir().unwrap();
+        let first = temp_dir.join("first");
+        let second = temp_dir.join("second");
+        let third = other_dir.join("third");
+        let second_thread = second.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(&second_thread, "second").unwrap();
+        });
+        let (path, mut file) = crate::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .open_any_when_created(&[&first, &second, &third])
+            .unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(path, second);
+        assert_eq!(contents, "second");
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn open_all() {
+        use std::io::Read;
+
+        let options = crate::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone());
+        let polling = options.clone().polling_only(std::time::Duration::from_millis(10));
+
+        for options in [options, polling] {
+            let temp_dir = mktemp::Temp::new_dir().unwrap();
+            let other_dir = mktemp::Temp::new_dir().unwrap();
+            let first = temp_dir.join("first");
+            let second = temp_dir.join("second");
+            let third = other_dir.join("third");
+            // Existing files are not waited for
+            std::fs::write(&first, "first").unwrap();
+            let second_thread = second.clone();
+            let third_thread = third.clone();
+            let thread = std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                std::fs::write(&third_thread, "third").unwrap();
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                std::fs::write(&second_thread, "second").unwrap();
+            });
+            let files = options.open_all_when_created(&[&first, &second, &third]).unwrap();
+            let contents = files.into_iter().map(|mut file| {
+                let mut contents = String::new();
+                file.read_to_string(&mut contents).unwrap();
+                contents
+            }).collect::<Vec<_>>();
+            assert_eq!(contents, ["first", "second", "third"]);
+            thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn open_all_order() {
+        use std::io::Read;
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let paths = (0..5).map(|i| temp_dir.join(i.to_string())).collect::<Vec<_>>();
+        let paths_thread = paths.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            for path in paths_thread.iter().rev() {
+                std::fs::write(path, path.file_name().unwrap().to_str().unwrap()).unwrap();
+            }
+        });
+        let path_refs = paths.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+        let files = crate::wait_all_created(&path_refs).unwrap();
+        let contents = files.into_iter().map(|mut file| {
+            let mut contents = String::new();
+            file.read_to_string(&mut contents).unwrap();
+            contents
+        }).collect::<Vec<_>>();
+        assert_eq!(contents, ["0", "1", "2", "3", "4"]);
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn open_all_lazy() {
+        use std::io::Read;
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let first = temp_dir.join("first");
+        let second = temp_dir.join("second");
+        std::fs::write(&first, "first").unwrap();
+        let second_thread = second.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(&second_thread, "second").unwrap();
+        });
+        let paths = [first.as_path(), second.as_path()];
+        let files = crate::wait_all_created_lazy(&paths).unwrap();
+        thread.join().unwrap();
+        let contents = files.map(|(path, file)| {
+            let mut contents = String::new();
+            file.unwrap().read_to_string(&mut contents).unwrap();
+            (path, contents)
+        }).collect::<Vec<_>>();
+        assert_eq!(contents, [(first, "first".to_owned()), (second, "second".to_owned())]);
+    }
+}
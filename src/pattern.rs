@@ -0,0 +1,211 @@
+//! Matching file names against glob patterns.
+//!
+//! Only single path components are matched so there's no special handling of `/`.
+
+use std::ffi::OsStr;
+
+/// Compiled glob pattern supporting `*`, `?`, character classes and escaping using `\`.
+pub(crate) struct Pattern {
+    tokens: Vec<Token>,
+}
+
+enum Token {
+    Char(char),
+    /// `?`
+    AnyChar,
+    /// `*`
+    AnyString,
+    /// `[...]` given as inclusive ranges
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+}
+
+impl Token {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Token::Char(expected) => *expected == c,
+            Token::AnyChar => true,
+            Token::AnyString => false,
+            Token::Class { ranges, negated } => ranges.iter().any(|(start, end)| (*start..=*end).contains(&c)) != *negated,
+        }
+    }
+}
+
+impl Pattern {
+    /// Compiles the pattern.
+    ///
+    /// A `[` without the closing `]` and a trailing `\` match themselves.
+    pub(crate) fn new(pattern: &str) -> Self {
+        let chars = pattern.chars().collect::<Vec<_>>();
+        let mut tokens = Vec::new();
+        let mut pos = 0;
+        while let Some(&c) = chars.get(pos) {
+            pos += 1;
+            let token = match c {
+                '*' => Token::AnyString,
+                '?' => Token::AnyChar,
+                '\\' if pos < chars.len() => {
+                    pos += 1;
+                    Token::Char(chars[pos - 1])
+                },
+                '[' => match parse_class(&chars[pos..]) {
+                    Some((class, len)) => {
+                        pos += len;
+                        class
+                    },
+                    None => Token::Char('['),
+                },
+                c => Token::Char(c),
+            };
+            tokens.push(token);
+        }
+
+        Pattern {
+            tokens,
+        }
+    }
+
+    /// Returns `true` if the whole name matches the pattern.
+    ///
+    /// Names which are not valid UTF-8 never match.
+    pub(crate) fn matches(&self, name: &OsStr) -> bool {
+        let name = match name.to_str() {
+            Some(name) => name.chars().collect::<Vec<_>>(),
+            None => return false,
+        };
+
+        let mut token_pos = 0;
+        let mut name_pos = 0;
+        // Position after the last `*` and the name position it was tried with
+        let mut backtrack = None;
+
+        while name_pos < name.len() {
+            match self.tokens.get(token_pos) {
+                Some(Token::AnyString) => {
+                    token_pos += 1;
+                    backtrack = Some((token_pos, name_pos));
+                    continue;
+                },
+                Some(token) if token.matches(name[name_pos]) => {
+                    token_pos += 1;
+                    name_pos += 1;
+                    continue;
+                },
+                _ => (),
+            }
+
+            // Mismatch - let the last `*` consume one more character
+            match &mut backtrack {
+                Some((star_token_pos, star_name_pos)) => {
+                    *star_name_pos += 1;
+                    token_pos = *star_token_pos;
+                    name_pos = *star_name_pos;
+                },
+                None => return false,
+            }
+        }
+
+        self.tokens[token_pos..].iter().all(|token| matches!(token, Token::AnyString))
+    }
+}
+
+/// Parses the character class following `[` returning it along with the number of characters
+/// including the closing `]`.
+///
+/// `!` or `^` at the start negates the class, `]` right after them is taken literally and `-`
+/// between two characters denotes a range. Returns `None` if the class is not terminated.
+fn parse_class(chars: &[char]) -> Option<(Token, usize)> {
+    let negated = matches!(chars.first(), Some('!') | Some('^'));
+    let start = usize::from(negated);
+    let mut pos = start;
+    let mut ranges = Vec::new();
+    loop {
+        let (first, next) = class_char(chars, pos)?;
+        if first == ']' && chars[pos] == ']' && pos > start {
+            return Some((Token::Class { ranges, negated }, pos + 1));
+        }
+        pos = next;
+        match (chars.get(pos), chars.get(pos + 1)) {
+            (Some('-'), Some(end)) if *end != ']' => {
+                let (last, next) = class_char(chars, pos + 1)?;
+                ranges.push((first, last));
+                pos = next;
+            },
+            _ => ranges.push((first, first)),
+        }
+    }
+}
+
+/// Returns the possibly escaped character of a class at `pos` and the position after it.
+fn class_char(chars: &[char], pos: usize) -> Option<(char, usize)> {
+    match chars.get(pos)? {
+        '\\' => chars.get(pos + 1).map(|c| (*c, pos + 2)),
+        c => Some((*c, pos + 1)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pattern;
+
+    #[test]
+    fn matches() {
+        let check = |pattern: &str, name: &str| Pattern::new(pattern).matches(name.as_ref());
+
+        assert!(check("report-*.json", "report-2021.json"));
+        assert!(check("report-*.json", "report-.json"));
+        assert!(!check("report-*.json", "report-2021.json.tmp"));
+        assert!(check("*", "anything"));
+        assert!(check("a*b*c", "abbbc"));
+        assert!(!check("a*b*c", "abbb"));
+        assert!(check("file-?", "file-1"));
+        assert!(!check("file-?", "file-"));
+        assert!(check("exact", "exact"));
+        assert!(!check("exact", "exactly"));
+        assert!(check("*.log", ".log"));
+        assert!(check("log.*", "log.1"));
+        assert!(!check("*.log", "log"));
+        assert!(check("**", ""));
+        assert!(check("", ""));
+        assert!(!check("", "a"));
+        assert!(!check("?", ""));
+    }
+
+    #[test]
+    fn classes() {
+        let check = |pattern: &str, name: &str| Pattern::new(pattern).matches(name.as_ref());
+
+        assert!(check("file-[0-9]", "file-7"));
+        assert!(!check("file-[0-9]", "file-a"));
+        assert!(check("file-[abc]", "file-b"));
+        assert!(check("file-[a-cx-z]", "file-y"));
+        assert!(!check("file-[a-cx-z]", "file-d"));
+        assert!(check("file-[!0-9]", "file-a"));
+        assert!(!check("file-[^0-9]", "file-5"));
+        // `]` right after the opening bracket and `-` at the end are literal
+        assert!(check("[]]", "]"));
+        assert!(check("[!]]", "a"));
+        assert!(check("[a-]", "-"));
+        assert!(check("*[0-9]*", "report-2021.json"));
+        assert!(!check("*[0-9]*", "report.json"));
+    }
+
+    #[test]
+    fn escapes() {
+        let check = |pattern: &str, name: &str| Pattern::new(pattern).matches(name.as_ref());
+
+        assert!(check("\\[1\\]", "[1]"));
+        assert!(!check("\\[1\\]", "1"));
+        assert!(check("\\*", "*"));
+        assert!(!check("\\*", "a"));
+        assert!(check("a\\?", "a?"));
+        assert!(check("[\\]]", "]"));
+        assert!(check("[a\\-z]", "-"));
+        assert!(!check("[a\\-z]", "b"));
+        // Unterminated class and trailing backslash are literal
+        assert!(check("[ab", "[ab"));
+        assert!(check("a\\", "a\\"));
+    }
+}
@@ -0,0 +1,251 @@
+//! Waiting for a file anywhere in a directory tree.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::{Change, ChangeSource, CreationKind, EventBuffer, Notification, OpenResult, Options, WaitContext, WaitError, WaitState, Wakeup, backend, check_notified, inotify_disabled, next_poll};
+
+impl Options {
+    /// Opens the file named `name` once it's available anywhere under `root`.
+    ///
+    /// The watches are not recursive so each directory of the tree is watched separately. The
+    /// existing tree is walked when waiting starts and each directory created later is watched as
+    /// soon as its creation is reported and then walked as well, so a file created in a new
+    /// directory before its watch was added is still found. The first matching file found is
+    /// returned along with its path. If multiple directories contain the file it's unspecified
+    /// which one is returned.
+    ///
+    /// Files found while walking a directory are considered ready just like an existing file is by
+    /// `open_when_created`. This is racy if the file is written right after its directory is
+    /// created: walking the new directory may find it before it's complete. The producer should
+    /// write the file under a different name and rename it or `wait_for_nonempty` and
+    /// `wait_for_quiescence` should be used in such case.
+    ///
+    /// Symlinks to directories are not followed which also avoids cycles. Directories moved
+    /// within the tree keep being watched but their files are looked up at the original path
+    /// until the tree is walked again, e.g. after `recheck_interval` elapses.
+    ///
+    /// Each directory takes one watch so big trees may exhaust the limit of watches
+    /// (`fs.inotify.max_user_watches` on Linux, the limit of open files with kqueue). If that
+    /// happens the polling fallback is used if enabled, walking the whole tree on each attempt.
+    pub fn open_when_created_recursive<P: AsRef<Path>>(&self, root: P, name: &OsStr) -> io::Result<(PathBuf, File)> {
+        let root = root.as_ref();
+        let context = self.wait_context(None);
+        self.wait_recursive(root, name, &context).map_err(Into::into)
+    }
+
+    fn wait_recursive(&self, root: &Path, name: &OsStr, context: &WaitContext<'_>) -> Result<(PathBuf, File), WaitError> {
+        if self.polling_only {
+            return self.poll_recursive(root, name, WaitError::InotifyInit(inotify_disabled()), context);
+        }
+
+        let mut options = self.clone();
+        options.watch_creations = true;
+        let mut watcher = match backend::DirWatcher::new(&options) {
+            Ok(watcher) => watcher,
+            Err(error) => return self.poll_recursive(root, name, WaitError::inotify_init(error), context),
+        };
+
+        let mut tree = Tree::default();
+        let mut buffer = EventBuffer::new(self);
+        let mut state = WaitState::watching(self.wait_config());
+        // Directories which may contain unseen entries
+        let mut pending = vec![root.to_path_buf()];
+        // Whether the already watched subdirectories have to be walked as well
+        let mut walk_all = false;
+        loop {
+            match self.walk(Some(&mut watcher), &mut tree, &mut pending, walk_all, root, name) {
+                Ok(Some(found)) => return self.found_recursive(found, context),
+                Ok(None) => (),
+                Err(error @ WaitError::AddWatch(_)) | Err(error @ WaitError::WatchLimit(_)) => return self.poll_recursive(root, name, error, context),
+                Err(error) => return Err(error),
+            }
+
+            walk_all = false;
+            match context.limited(self.recheck_interval).wait_readable(&watcher) {
+                Ok(Wakeup::Readable) => (),
+                // Time to re-check in case events were lost
+                Ok(Wakeup::TimedOut) if !context.is_expired() => {
+                    walk_all = true;
+                    pending.push(root.to_path_buf());
+                    continue;
+                },
+                Ok(Wakeup::TimedOut) => return Err(context.timed_out()),
+                Ok(Wakeup::Cancelled) => return Err(WaitError::Cancelled),
+                Err(error) => return self.poll_recursive(root, name, WaitError::ReadEvents(error), context),
+            }
+
+            let mut candidates = Vec::new();
+            let mut removed = Vec::new();
+            let dirs = &tree.dirs;
+            let result = watcher.read_changes(&mut buffer, |watch, change| {
+                if let Change::Overflow = change {
+                    walk_all = true;
+                    pending.push(root.to_path_buf());
+                    return;
+                }
+
+                let dir = match dirs.get(watch) {
+                    Some(dir) => dir,
+                    None => return,
+                };
+                match change {
+                    Change::Entry(entry, kind) if entry == name => if self.is_created(kind) {
+                        candidates.push((dir.join(entry), kind));
+                    },
+                    // May be a new directory
+                    Change::Entry(entry, CreationKind::Created) | Change::Entry(entry, CreationKind::MovedTo) => pending.push(dir.join(entry)),
+                    Change::Entry(_, _) => (),
+                    Change::Unknown => pending.push(dir.clone()),
+                    Change::Removed => removed.push(dir.clone()),
+                    Change::Overflow => (),
+                }
+            });
+            match result {
+                Ok(()) => (),
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => (),
+                Err(error) => return self.poll_recursive(root, name, WaitError::ReadEvents(error), context),
+            }
+
+            for dir in removed {
+                if dir == root {
+                    return Err(WaitError::ParentRemoved);
+                }
+                tree.dirs.retain(|_, watched| *watched != dir);
+                tree.watched.remove(&dir);
+            }
+
+            for (path, kind) in candidates {
+                if let Some(file) = check_notified(&mut state, Notification::Available(kind), context, |kind| self.attempt_open(&path, kind))? {
+                    return self.found_recursive((path, file), context);
+                }
+            }
+        }
+    }
+
+    fn poll_recursive(&self, root: &Path, name: &OsStr, inotify_error: WaitError, context: &WaitContext<'_>) -> Result<(PathBuf, File), WaitError> {
+        if self.only_accept_rename {
+            return Err(inotify_error);
+        }
+        let mut state = WaitState::polling(self.wait_config());
+        loop {
+            context.check_cancelled()?;
+
+            let mut tree = Tree::default();
+            match self.walk(None, &mut tree, &mut vec![root.to_path_buf()], true, root, name) {
+                Ok(Some(found)) => return self.found_recursive(found, context),
+                Ok(None) => (),
+                // The root doesn't exist yet
+                Err(WaitError::Open(error)) if error.kind() == io::ErrorKind::NotFound => (),
+                Err(error) => return Err(error),
+            }
+
+            if !next_poll(&mut state, OpenResult::NotFound, context)? {
+                return Err(inotify_error);
+            }
+        }
+    }
+
+    fn found_recursive(&self, (path, file): (PathBuf, File), context: &WaitContext<'_>) -> Result<(PathBuf, File), WaitError> {
+        self.wait_unchanged(&path, context)?;
+        Ok((path, file))
+    }
+
+    /// Watches and reads the pending directories and their new subdirectories.
+    ///
+    /// Returns the file if it was found in any of them. The directories are watched before
+    /// reading them so that no entry is missed. Subdirectories which are already watched are
+    /// skipped unless `walk_all` is `true`.
+    // The watch ID is `Copy` only on some platforms
+    #[allow(clippy::clone_on_copy)]
+    fn walk(&self, mut watcher: Option<&mut backend::DirWatcher>, tree: &mut Tree, pending: &mut Vec<PathBuf>, walk_all: bool, root: &Path, name: &OsStr) -> Result<Option<(PathBuf, File)>, WaitError> {
+        while let Some(dir) = pending.pop() {
+            // Entries reported by events may be anything, symlinks are not followed
+            if dir != root {
+                match std::fs::symlink_metadata(&dir) {
+                    Ok(metadata) if metadata.is_dir() => (),
+                    Ok(_) => continue,
+                    Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+                    Err(error) => return Err(WaitError::Open(error)),
+                }
+            }
+
+            if !tree.watched.insert(dir.clone()) {
+                // Already watched, only reading it
+            } else if let Some(watcher) = watcher.as_deref_mut() {
+                match watcher.watch(&dir) {
+                    Ok(watch) => { tree.dirs.insert(watch.clone(), dir.clone()); },
+                    // Removed in the meantime
+                    Err(error) if error.kind() == io::ErrorKind::NotFound && dir != root => {
+                        tree.watched.remove(&dir);
+                        continue;
+                    },
+                    Err(error) => return Err(WaitError::add_watch(error)),
+                }
+            }
+
+            let entries = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(error) if error.kind() == io::ErrorKind::NotFound && dir != root => continue,
+                Err(error) => return Err(WaitError::Open(error)),
+            };
+            for entry in entries {
+                let entry = entry.map_err(WaitError::Open)?;
+                let path = entry.path();
+                if entry.file_name() == name {
+                    match self.attempt_open(&path, CreationKind::NoEvent) {
+                        Ok(Some(file)) => return Ok(Some((path, file))),
+                        Ok(None) => (),
+                        Err(error) if error.kind() == io::ErrorKind::NotFound => (),
+                        Err(error) => return Err(WaitError::Open(error)),
+                    }
+                }
+                if entry.file_type().map_err(WaitError::Open)?.is_dir() && (walk_all || !tree.watched.contains(&path)) {
+                    pending.push(path);
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Directories of the tree seen so far.
+#[derive(Default)]
+struct Tree {
+    dirs: HashMap<backend::WatchId, PathBuf>,
+    // Watched directories, only walked ones when polling
+    watched: HashSet<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn open_when_created_recursive() {
+        use std::io::Read;
+
+        let options = crate::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone());
+        let polling = options.clone().polling_only(std::time::Duration::from_millis(10));
+
+        for options in [options, polling] {
+            let temp_dir = mktemp::Temp::new_dir().unwrap();
+            std::fs::create_dir_all(temp_dir.join("existing").join("empty")).unwrap();
+            let nested = temp_dir.join("existing").join("new").join("deeper");
+            let nested_thread = nested.clone();
+            let thread = std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                // The file is created right away, possibly before the new directories are watched
+                std::fs::create_dir_all(&nested_thread).unwrap();
+                std::fs::write(nested_thread.join("done.tmp"), "satoshi nakamoto").unwrap();
+                std::fs::rename(nested_thread.join("done.tmp"), nested_thread.join("done.marker")).unwrap();
+            });
+            let (path, mut file) = options.open_when_created_recursive(&temp_dir, "done.marker".as_ref()).unwrap();
+            let mut contents = String::new();
+            file.read_to_string(&mut contents).unwrap();
+            assert_eq!(path, nested.join("done.marker"));
+            assert_eq!(contents, "satoshi nakamoto");
+            thread.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! Sharing a single wait between threads waiting for the same file.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use crate::{Options, split_path};
+
+/// Coalesces concurrent waits for the same file.
+///
+/// Threads waiting for the same path using `open_when_created` share a single wait and thus a
+/// single watch. The first thread waits for the file as `Options::open_when_created` does and the
+/// other ones block until it's done. Each of them then opens the file on its own so they all get
+/// their own `File`. If the file is gone by the time a thread opens it that thread starts waiting
+/// again.
+///
+/// The paths are identified by their canonicalized parent directory so different paths leading
+/// to the same file share the wait as well. Once the wait finishes it's removed from the registry
+/// and the following calls start a new one.
+///
+/// The timeout and other options apply to the shared wait, so a thread joining an already running
+/// wait may time out sooner than it would on its own. If the wait fails all the threads sharing it
+/// get an error of the same kind and message.
+///
+/// The waiter is `Sync` so it can be shared using a reference or `Arc`.
+#[derive(Debug)]
+pub struct SharedWaiter {
+    options: Options,
+    waits: Mutex<HashMap<PathBuf, Arc<Wait>>>,
+}
+
+impl SharedWaiter {
+    /// Creates the registry waiting for the files using `options`.
+    pub fn new(options: Options) -> Self {
+        SharedWaiter {
+            options,
+            waits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Opens the file once it's available sharing the wait with other threads waiting for it.
+    pub fn open_when_created<P: AsRef<Path>>(&self, path: P) -> io::Result<File> {
+        let path = path.as_ref();
+        let key = canonical_key(path);
+        loop {
+            let (wait, leader) = {
+                let mut waits = self.waits.lock().unwrap_or_else(PoisonError::into_inner);
+                match waits.get(&key) {
+                    Some(wait) => (Arc::clone(wait), false),
+                    None => {
+                        let wait = Arc::new(Wait::default());
+                        waits.insert(key.clone(), Arc::clone(&wait));
+                        (wait, true)
+                    },
+                }
+            };
+
+            if leader {
+                let mut finish = Finish { waiter: self, key: &key, wait: &wait, result: None };
+                let result = self.options.open_when_created(path);
+                finish.result = Some(match &result {
+                    Ok(_) => Ok(()),
+                    Err(error) => Err((error.kind(), error.to_string())),
+                });
+                return result;
+            }
+
+            match wait.finished() {
+                Ok(()) => match self.options.open_ready(path) {
+                    // Deleted in the meantime
+                    Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+                    result => return result,
+                },
+                Err((kind, message)) => return Err(io::Error::new(kind, message)),
+            }
+        }
+    }
+}
+
+type WaitResult = Result<(), (io::ErrorKind, String)>;
+
+/// Single wait shared by multiple threads.
+#[derive(Debug, Default)]
+struct Wait {
+    // `None` until the wait finishes
+    result: Mutex<Option<WaitResult>>,
+    finished: Condvar,
+}
+
+impl Wait {
+    /// Blocks until the thread waiting for the file is done.
+    fn finished(&self) -> WaitResult {
+        let result = self.result.lock().unwrap_or_else(PoisonError::into_inner);
+        let result = self.finished.wait_while(result, |result| result.is_none()).unwrap_or_else(PoisonError::into_inner);
+        result.clone().expect("the wait finished")
+    }
+}
+
+/// Removes the wait from the registry and wakes up the other threads even if waiting panicked.
+struct Finish<'a> {
+    waiter: &'a SharedWaiter,
+    key: &'a Path,
+    wait: &'a Wait,
+    result: Option<WaitResult>,
+}
+
+impl Drop for Finish<'_> {
+    fn drop(&mut self) {
+        self.waiter.waits.lock().unwrap_or_else(PoisonError::into_inner).remove(self.key);
+        let result = self.result.take().unwrap_or_else(|| Err((io::ErrorKind::Other, "the thread waiting for the file panicked".to_owned())));
+        *self.wait.result.lock().unwrap_or_else(PoisonError::into_inner) = Some(result);
+        self.wait.finished.notify_all();
+    }
+}
+
+/// Identifies the file by its canonicalized parent directory.
+///
+/// The file itself may not exist yet so it can not be canonicalized.
+fn canonical_key(path: &Path) -> PathBuf {
+    split_path(path)
+        .and_then(|(dir, file_name)| dir.canonicalize().ok().map(|dir| dir.join(file_name)))
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn shared_waiter() {
+        use std::io::Read;
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let options = crate::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone());
+        let waiter = super::SharedWaiter::new(options);
+        std::thread::scope(|scope| {
+            let threads = (0..4).map(|i| {
+                // Different paths to the same file share the wait
+                let path = if i % 2 == 0 { file_path.clone() } else { temp_dir.join(".").join("test") };
+                let waiter = &waiter;
+                scope.spawn(move || {
+                    let mut contents = String::new();
+                    waiter.open_when_created(path).unwrap().read_to_string(&mut contents).unwrap();
+                    contents
+                })
+            }).collect::<Vec<_>>();
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            assert_eq!(waiter.waits.lock().unwrap().len(), 1);
+            std::fs::write(&file_path, "satoshi nakamoto").unwrap();
+            for thread in threads {
+                assert_eq!(thread.join().unwrap(), "satoshi nakamoto");
+            }
+        });
+        assert!(waiter.waits.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,326 @@
+//! The waiting logic separated from the I/O.
+//!
+//! The order of opening and waiting as well as the decisions about retrying are the tricky part of
+//! waiting for the file. They live here so that they can be reused with custom I/O and tested
+//! without a filesystem. The methods of `Options` are just drivers performing the actions.
+
+use core::time::Duration;
+use crate::CreationKind;
+
+/// Settings of the waiting logic.
+///
+/// This is the part of `Options` the state machine needs, independent of how the file is opened
+/// or how the changes are watched. `Options::wait_config` returns the settings of the options.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct WaitConfig {
+    /// Keep waiting if the file was reported to be available but it's gone when opened.
+    ///
+    /// See `Options::retry_on_fluke`.
+    pub retry_flukes: bool,
+    /// The maximum number of flukes retried, unlimited if `None`.
+    ///
+    /// See `Options::max_fluke_retries`.
+    pub max_fluke_retries: Option<usize>,
+    /// How long to sleep after a fluke before waiting again.
+    ///
+    /// See `Options::fluke_cooldown`.
+    pub fluke_cooldown: Option<Duration>,
+    /// The delays between attempts when polling, polling is disabled if `None`.
+    pub polling: Option<Polling>,
+}
+
+/// Delays between attempts to open the file when polling.
+///
+/// The first delay is `initial` and each following one is `factor` times longer, up to `max`.
+/// Constant interval is represented as backoff with factor `1.0`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Polling {
+    initial: Duration,
+    max: Duration,
+    factor: f64,
+}
+
+impl Polling {
+    /// Polls with exponentially growing delays.
+    ///
+    /// `initial` greater than `max` is clamped to `max`.
+    ///
+    /// # Panics
+    ///
+    /// This panics if `factor` is not finite or is less than `1.0`.
+    pub fn new(initial: Duration, max: Duration, factor: f64) -> Self {
+        assert!(factor.is_finite() && factor >= 1.0, "the backoff factor must be finite and at least 1.0, got {}", factor);
+        Polling {
+            initial: initial.min(max),
+            max,
+            factor,
+        }
+    }
+
+    /// Polls with the same delay between all attempts.
+    pub fn constant(interval: Duration) -> Self {
+        Polling {
+            initial: interval,
+            max: interval,
+            factor: 1.0,
+        }
+    }
+
+    /// The first delay and the delay after the file was found to exist but not to be ready.
+    pub fn initial(&self) -> Duration {
+        self.initial
+    }
+
+    /// The longest delay.
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// How many times each delay is longer than the previous one.
+    pub fn factor(&self) -> f64 {
+        self.factor
+    }
+}
+
+/// Tracks the delay between polling attempts.
+#[derive(Debug)]
+pub(crate) struct Backoff {
+    polling: Polling,
+    current: Duration,
+}
+
+impl Backoff {
+    pub(crate) fn new(polling: Polling) -> Self {
+        Backoff {
+            polling,
+            current: polling.initial,
+        }
+    }
+
+    /// Returns the delay to sleep for and advances to the next one.
+    pub(crate) fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = Duration::try_from_secs_f64(delay.as_secs_f64() * self.polling.factor)
+            .unwrap_or(self.polling.max)
+            .min(self.polling.max);
+        delay
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.current = self.polling.initial;
+    }
+}
+
+/// Result of an attempt to open the file performed by the driver.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum OpenResult {
+    /// The file was opened and it's ready.
+    Opened,
+    /// The file exists but it's not ready yet, e.g. it's still empty.
+    NotReady,
+    /// The file doesn't exist.
+    NotFound,
+    /// Opening failed with an error other than not found.
+    Failed,
+}
+
+/// What the watch reported since the driver started waiting.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Notification {
+    /// The file was reported to be available.
+    Available(CreationKind),
+    /// The directory changed in an unknown way so the file might be available.
+    Maybe,
+    /// Nothing was reported but it's time to check again in case events were lost.
+    Recheck,
+}
+
+/// What the driver should do next.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WaitAction {
+    /// Attempt to open the file and report the result using `WaitState::open_result`.
+    ///
+    /// The kind is the kind of the last event concerning the file.
+    Open(CreationKind),
+    /// Wait for changes in the watched directory and report them using `WaitState::notify`.
+    Wait,
+    /// Sleep for the duration and call `WaitState::slept`.
+    Sleep(Duration),
+    /// Stop waiting.
+    Return(WaitOutcome),
+}
+
+/// How waiting ended.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WaitOutcome {
+    /// The file opened by the last attempt should be returned.
+    Opened(CreationKind),
+    /// The error of the last attempt should be returned.
+    Failed,
+    /// The file kept disappearing more times than allowed, the error of the last attempt should
+    /// be returned.
+    FlukeRetriesExhausted,
+    /// Polling is disabled so waiting isn't possible without the watch.
+    Unavailable,
+}
+
+/// State machine deciding what to do while waiting for the file.
+///
+/// The driver starts by calling `start` and then performs the returned actions feeding their
+/// results back until `WaitAction::Return` is returned. The file is always opened before waiting
+/// so the watch has to be set up before calling `start` to avoid missing the file.
+#[derive(Debug)]
+pub struct WaitState {
+    config: WaitConfig,
+    kind: CreationKind,
+    reported_available: bool,
+    flukes: usize,
+    // Sleeping after a fluke rather than between polling attempts
+    cooling_down: bool,
+    // `None` when waiting using the watch
+    backoff: Option<Backoff>,
+    polling: bool,
+}
+
+impl WaitState {
+    /// Creates the state for waiting using a watch of the parent directory.
+    pub fn watching(config: WaitConfig) -> Self {
+        WaitState::new(config, false)
+    }
+
+    /// Creates the state for periodically checking the file when the watch is not available.
+    ///
+    /// If the polling fallback is disabled waiting ends with `WaitOutcome::Unavailable` after the
+    /// first unsuccessful attempt.
+    pub fn polling(config: WaitConfig) -> Self {
+        WaitState::new(config, true)
+    }
+
+    fn new(config: WaitConfig, polling: bool) -> Self {
+        WaitState {
+            config,
+            kind: CreationKind::NoEvent,
+            reported_available: false,
+            flukes: 0,
+            cooling_down: false,
+            backoff: if polling { config.polling.map(Backoff::new) } else { None },
+            polling,
+        }
+    }
+
+    /// Returns the first action.
+    pub fn start(&mut self) -> WaitAction {
+        WaitAction::Open(self.kind)
+    }
+
+    /// Decides what to do after an attempt to open the file.
+    pub fn open_result(&mut self, result: OpenResult) -> WaitAction {
+        match result {
+            OpenResult::Opened => return WaitAction::Return(WaitOutcome::Opened(self.kind)),
+            OpenResult::Failed => return WaitAction::Return(WaitOutcome::Failed),
+            OpenResult::NotReady => self.backoff.iter_mut().for_each(Backoff::reset),
+            // The file was reported to be available but it's gone
+            OpenResult::NotFound if self.reported_available => {
+                if !self.config.retry_flukes {
+                    return WaitAction::Return(WaitOutcome::Failed);
+                }
+                if self.config.max_fluke_retries.is_some_and(|max| self.flukes >= max) {
+                    return WaitAction::Return(WaitOutcome::FlukeRetriesExhausted);
+                }
+                self.flukes += 1;
+                if let (Some(cooldown), false) = (self.config.fluke_cooldown, self.polling) {
+                    self.cooling_down = true;
+                    return WaitAction::Sleep(cooldown);
+                }
+            },
+            OpenResult::NotFound => (),
+        }
+
+        if !self.polling {
+            return WaitAction::Wait;
+        }
+        match &mut self.backoff {
+            Some(backoff) => WaitAction::Sleep(backoff.next_delay()),
+            None => WaitAction::Return(WaitOutcome::Unavailable),
+        }
+    }
+
+    /// Decides what to do after waiting for changes.
+    pub fn notify(&mut self, notification: Notification) -> WaitAction {
+        let (kind, reported_available) = match notification {
+            Notification::Available(kind) => (kind, true),
+            Notification::Maybe => (CreationKind::DirectoryChanged, false),
+            Notification::Recheck => (CreationKind::NoEvent, false),
+        };
+        self.kind = kind;
+        self.reported_available = reported_available;
+        WaitAction::Open(kind)
+    }
+
+    /// Decides what to do after sleeping between polling attempts or after a fluke.
+    pub fn slept(&mut self) -> WaitAction {
+        if self.cooling_down {
+            // The events received in the meantime are still queued
+            self.cooling_down = false;
+            return WaitAction::Wait;
+        }
+        WaitAction::Open(CreationKind::NoEvent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Notification, OpenResult, Polling, WaitAction, WaitConfig, WaitOutcome, WaitState};
+    use crate::CreationKind;
+
+    #[test]
+    fn flukes() {
+        let config = WaitConfig { retry_flukes: true, max_fluke_retries: Some(1), ..WaitConfig::default() };
+        let mut state = WaitState::watching(config);
+        assert_eq!(state.start(), WaitAction::Open(CreationKind::NoEvent));
+        assert_eq!(state.open_result(OpenResult::NotFound), WaitAction::Wait);
+        assert_eq!(state.notify(Notification::Available(CreationKind::MovedTo)), WaitAction::Open(CreationKind::MovedTo));
+        assert_eq!(state.open_result(OpenResult::NotFound), WaitAction::Wait);
+        // Unrelated changes don't count as flukes
+        assert_eq!(state.notify(Notification::Maybe), WaitAction::Open(CreationKind::DirectoryChanged));
+        assert_eq!(state.open_result(OpenResult::NotFound), WaitAction::Wait);
+        assert_eq!(state.notify(Notification::Available(CreationKind::ClosedWrite)), WaitAction::Open(CreationKind::ClosedWrite));
+        assert_eq!(state.open_result(OpenResult::NotFound), WaitAction::Return(WaitOutcome::FlukeRetriesExhausted));
+
+        let cooldown = std::time::Duration::from_millis(10);
+        let mut state = WaitState::watching(WaitConfig { fluke_cooldown: Some(cooldown), ..config });
+        state.start();
+        state.notify(Notification::Available(CreationKind::MovedTo));
+        assert_eq!(state.open_result(OpenResult::NotFound), WaitAction::Sleep(cooldown));
+        assert_eq!(state.slept(), WaitAction::Wait);
+
+        let config = WaitConfig { retry_flukes: false, ..config };
+        let mut state = WaitState::watching(config);
+        state.start();
+        state.notify(Notification::Available(CreationKind::ClosedWrite));
+        assert_eq!(state.open_result(OpenResult::NotFound), WaitAction::Return(WaitOutcome::Failed));
+        let mut state = WaitState::watching(config);
+        state.start();
+        state.notify(Notification::Recheck);
+        assert_eq!(state.open_result(OpenResult::Opened), WaitAction::Return(WaitOutcome::Opened(CreationKind::NoEvent)));
+    }
+
+    #[test]
+    fn polling() {
+        use std::time::Duration;
+
+        let mut state = WaitState::polling(WaitConfig::default());
+        assert_eq!(state.start(), WaitAction::Open(CreationKind::NoEvent));
+        assert_eq!(state.open_result(OpenResult::NotFound), WaitAction::Return(WaitOutcome::Unavailable));
+
+        let polling = Polling::new(Duration::from_millis(10), Duration::from_millis(40), 2.0);
+        let mut state = WaitState::polling(WaitConfig { polling: Some(polling), ..WaitConfig::default() });
+        state.start();
+        assert_eq!(state.open_result(OpenResult::NotFound), WaitAction::Sleep(Duration::from_millis(10)));
+        assert_eq!(state.slept(), WaitAction::Open(CreationKind::NoEvent));
+        assert_eq!(state.open_result(OpenResult::NotFound), WaitAction::Sleep(Duration::from_millis(20)));
+        // The file appeared so the producer is active
+        assert_eq!(state.open_result(OpenResult::NotReady), WaitAction::Sleep(Duration::from_millis(10)));
+        assert_eq!(state.open_result(OpenResult::Opened), WaitAction::Return(WaitOutcome::Opened(CreationKind::NoEvent)));
+    }
+}
@@ -0,0 +1,253 @@
+//! Asynchronous waiting integrated with tokio.
+
+#[cfg(unix)]
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+#[cfg(unix)]
+use tokio::io::unix::AsyncFd;
+#[cfg(unix)]
+use crate::{CreationKind, EventBuffer, Notification, Readiness, WaitError, backend, scan_changes};
+#[cfg(windows)]
+use crate::CancellationToken;
+use crate::{Options, WaitAction, WaitOutcome, WaitState, file_state, inotify_disabled, open_result, take_error, take_opened, timed_out};
+
+impl Options {
+    /// Opens the file once it's available without blocking the thread.
+    ///
+    /// This is the asynchronous version of `open_when_created` which registers the inotify file
+    /// descriptor with the tokio reactor. The polling fallback uses `tokio::time::sleep` instead of
+    /// sleeping the thread.
+    ///
+    /// It honors the same settings as the blocking version with these exceptions on Unix:
+    ///
+    /// * `wait_for_parent` is ignored, a missing parent directory fails the watch so this falls
+    ///   back to polling if it's enabled or returns an error otherwise
+    /// * `on_parent_removed` and `recreate_watch_on_ignored` are ignored, removal of the parent
+    ///   directory is handled as if `ParentRemovedPolicy::FallBackToPolling` was set
+    /// * the clock set by `with_clock` is not used, the timeout and sleeps use the tokio timer
+    /// * `heartbeat` callbacks are not called
+    ///
+    /// On Windows the blocking version runs in a thread so all settings are honored.
+    ///
+    /// Waiting can be cancelled by dropping the returned future.
+    ///
+    /// This method must be called within tokio runtime with IO and time drivers enabled. On Windows
+    /// the events can not be registered with the reactor so waiting happens in a blocking thread
+    /// of the runtime which is stopped when the future is dropped.
+    #[inline]
+    pub async fn open_when_created_async<P: AsRef<Path>>(&self, path: P) -> io::Result<File> {
+        // Monomorphise the method to avoid machine code blowup
+        self.internal_open_when_created_async(path.as_ref()).await
+    }
+
+    async fn internal_open_when_created_async(&self, path: &Path) -> io::Result<File> {
+        match self.time_limit() {
+            Some(timeout) => {
+                tokio::time::timeout(timeout, self.wait_async(path))
+                    .await
+                    .unwrap_or_else(|_| Err(timed_out()))
+            },
+            None => self.wait_async(path).await,
+        }
+    }
+
+    #[cfg(unix)]
+    async fn wait_async(&self, path: &Path) -> io::Result<File> {
+        if self.polling_only {
+            return self.try_fallback_open_async(path, inotify_disabled()).await;
+        }
+
+        // Fast path avoiding inotify syscalls if the file is already there.
+        match self.attempt_open(path, CreationKind::NoEvent) {
+            Ok(Some(file)) => {
+                self.wait_unchanged_async(path).await?;
+                return Ok(file);
+            },
+            Ok(None) => (),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => (),
+            Err(error) => return Err(error),
+        }
+
+        let (dir, file_name) = match self.watch_target(path) {
+            Err(error) => return Err(WaitError::AddWatch(error).into()),
+            Ok(Some(split)) => split,
+            Ok(None) => return self.try_fallback_open_async(path, io::Error::new(io::ErrorKind::InvalidInput, "the path doesn't contain a file name")).await,
+        };
+
+        let mut watcher = match backend::DirWatcher::new(self) {
+            Ok(watcher) => watcher,
+            Err(error) => return self.try_fallback_open_async(path, WaitError::inotify_init(error).into()).await,
+        };
+
+        if let Err(error) = watcher.watch(&dir) {
+            return self.try_fallback_open_async(path, WaitError::add_watch(error).into()).await;
+        }
+
+        match AsyncFd::new(watcher) {
+            Ok(watcher) => self.wait_for_file_async(watcher, path, file_name).await,
+            Err(error) => self.try_fallback_open_async(path, error).await,
+        }
+    }
+
+    #[cfg(windows)]
+    async fn wait_async(&self, path: &Path) -> io::Result<File> {
+        if self.polling_only {
+            return self.try_fallback_open_async(path, inotify_disabled()).await;
+        }
+
+        let token = CancellationToken::new()?;
+        let _guard = CancelOnDrop(token.clone());
+        let options = self.clone();
+        let path = path.to_owned();
+        tokio::task::spawn_blocking(move || options.open_when_created_cancellable(path, &token))
+            .await
+            .map_err(io::Error::other)?
+    }
+
+    async fn try_fallback_open_async(&self, path: &Path, inotify_error: io::Error) -> io::Result<File> {
+        if self.only_accept_rename {
+            return Err(inotify_error);
+        }
+        let mut state = WaitState::polling(self.wait_config());
+        let mut last = None;
+        let mut action = state.start();
+        loop {
+            action = match action {
+                WaitAction::Open(kind) => state.open_result(open_result(self.attempt_open(path, kind), &mut last)),
+                WaitAction::Sleep(delay) => {
+                    tokio::time::sleep(delay).await;
+                    state.slept()
+                },
+                WaitAction::Wait => unreachable!("polling never waits for events"),
+                WaitAction::Return(WaitOutcome::Opened(_)) => {
+                    let file = take_opened(&mut last);
+                    self.wait_unchanged_async(path).await?;
+                    return Ok(file);
+                },
+                WaitAction::Return(WaitOutcome::Unavailable) => return Err(inotify_error),
+                WaitAction::Return(outcome) => return Err(take_error(&mut last, outcome).into()),
+            }
+        }
+    }
+
+    /// Asynchronous version of `wait_unchanged`.
+    async fn wait_unchanged_async(&self, path: &Path) -> io::Result<()> {
+        let quiet = match self.quiescence {
+            Some(quiet) => quiet,
+            None => return Ok(()),
+        };
+
+        let mut last = file_state(path)?;
+        loop {
+            tokio::time::sleep(quiet).await;
+            let current = file_state(path)?;
+            if current == last {
+                return Ok(());
+            }
+            last = current;
+        }
+    }
+
+    #[cfg(unix)]
+    async fn wait_for_file_async(&self, mut watcher: AsyncFd<backend::DirWatcher>, path: &Path, file_name: &OsStr) -> io::Result<File> {
+        let mut buffer = EventBuffer::new(self);
+        let mut state = WaitState::watching(self.wait_config());
+        let mut last = None;
+        let mut action = state.start();
+
+        loop {
+            action = match action {
+                WaitAction::Open(kind) => state.open_result(open_result(self.attempt_open(path, kind), &mut last)),
+                WaitAction::Wait => {
+                    let readiness = loop {
+                        let readable = match self.recheck_interval {
+                            Some(interval) => match tokio::time::timeout(interval, watcher.readable_mut()).await {
+                                Ok(readable) => readable,
+                                // Time to re-check in case events were lost
+                                Err(_) => break None,
+                            },
+                            None => watcher.readable_mut().await,
+                        };
+                        let mut guard = match readable {
+                            Ok(guard) => guard,
+                            Err(error) => return self.try_fallback_open_async(path, error).await,
+                        };
+
+                        match scan_changes(guard.get_inner_mut(), &mut buffer, file_name) {
+                            Ok(Some(readiness)) => break Some(readiness),
+                            Ok(None) => (),
+                            Err(error) if error.kind() == io::ErrorKind::WouldBlock => guard.clear_ready(),
+                            Err(error) => return self.try_fallback_open_async(path, error).await,
+                        }
+                    };
+
+                    state.notify(match readiness {
+                        Some(Readiness::Available(kind)) => Notification::Available(kind),
+                        Some(Readiness::Maybe) => Notification::Maybe,
+                        None => Notification::Recheck,
+                    })
+                },
+                WaitAction::Sleep(cooldown) => {
+                    tokio::time::sleep(cooldown).await;
+                    state.slept()
+                },
+                WaitAction::Return(WaitOutcome::Opened(_)) => {
+                    let file = take_opened(&mut last);
+                    self.wait_unchanged_async(path).await?;
+                    return Ok(file);
+                },
+                WaitAction::Return(outcome) => return Err(take_error(&mut last, outcome).into()),
+            }
+        }
+    }
+}
+
+/// Stops the blocking wait if the future waiting for it is dropped.
+#[cfg(windows)]
+struct CancelOnDrop(CancellationToken);
+
+#[cfg(windows)]
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[tokio::test]
+    async fn wait_async() {
+        use std::io::Read;
+
+        let test_string = "satoshi nakamoto";
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(&file_path_thread, test_string).unwrap();
+        });
+        let mut file = crate::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .open_when_created_async(&file_path)
+            .await
+            .unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, test_string);
+        thread.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn timeout_async() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let error = crate::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone())
+            .timeout(std::time::Duration::from_millis(100))
+            .open_when_created_async(&file_path)
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+    }
+}
@@ -0,0 +1,392 @@
+//! Watching a directory for newly created files.
+
+use std::cell::Cell;
+use std::collections::{HashSet, VecDeque};
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use crate::pattern::Pattern;
+use crate::{Change, ChangeSource, CreationKind, EventBuffer, Mechanism, Notification, OpenResult, Options, WaitContext, WaitState, Wakeup, backend, cancelled, check_notified, inotify_disabled, next_poll, timed_out};
+
+impl Options {
+    /// Watches the directory yielding files created in it whose names match `filter`.
+    ///
+    /// Each call to `next()` blocks until a new entry matching `filter` is created, opens it and
+    /// yields it along with its name. Entries existing before this was called are not yielded.
+    /// Entries created while the previous file is being processed are not lost since the
+    /// directory stays watched for the whole lifetime of the iterator.
+    ///
+    /// The file is considered created when it's moved into the directory or closed after writing.
+    /// If `assume_create_is_atomic` is `true` it's considered created right when it appears in the
+    /// directory. Note that closing a file after rewriting it counts as creation too.
+    ///
+    /// If the inotify queue overflows entries created before the first overflow may be missed since
+    /// there's no earlier snapshot of the directory to compare with. Later overflows are handled
+    /// by comparing the directory with such snapshot.
+    ///
+    /// The timeout, if set, applies to each call to `next()` separately. Errors are yielded
+    /// without ending the iteration except for errors of the watch itself after which the
+    /// iteration ends.
+    pub fn watch_directory<F: Fn(&OsStr) -> bool>(&self, dir: &Path, filter: F) -> DirectoryWatch<'_, F> {
+        let mut watch = DirectoryWatch {
+            options: self,
+            dir: dir.to_path_buf(),
+            filter,
+            watcher: None,
+            known: None,
+            pending: VecDeque::new(),
+            state: WaitState::watching(self.wait_config()),
+            polling: WaitState::polling(self.wait_config()),
+            rescanned: OpenResult::NotFound,
+            buffer: EventBuffer::new(self),
+            error: None,
+            finished: false,
+            deadline: None,
+        };
+
+        let result = if self.polling_only {
+            Err(inotify_disabled())
+        } else {
+            backend::DirWatcher::new(self).and_then(|mut watcher| {
+                watcher.watch(dir)?;
+                Ok(watcher)
+            })
+        };
+
+        match result {
+            Ok(watcher) => watch.watcher = Some(watcher),
+            Err(error) if self.polling_fallback.is_none() => watch.error = Some(error),
+            Err(_) => (),
+        }
+
+        // Without names of the entries we have to find out which are new ourselves.
+        if watch.error.is_none() && (watch.watcher.is_none() || !backend::REPORTS_NAMES) {
+            match watch.read_entries() {
+                Ok(known) => watch.known = Some(known),
+                Err(error) => watch.error = Some(error),
+            }
+        }
+
+        watch
+    }
+
+    /// Opens the first file in `dir` whose name matches the glob `pattern`.
+    ///
+    /// The pattern supports `*` matching any number of characters, `?` matching exactly one
+    /// character and classes such as `[a-z0-9_]` or `[!.]` matching one of the characters or
+    /// ranges, or any other if negated. Special characters can be escaped using `\`. Files
+    /// already existing in the directory are checked first in unspecified order and then the
+    /// directory is watched the same way as in `watch_directory`. The path of the opened file is
+    /// returned along with it.
+    pub fn open_matching_when_created(&self, dir: &Path, pattern: &str) -> io::Result<(PathBuf, File)> {
+        let pattern = Pattern::new(pattern);
+        // The watch has to be set up before checking existing entries to avoid races
+        let mut watch = self.watch_directory(dir, |name| pattern.matches(name));
+        watch.deadline = self.deadline_from(self.clock().now());
+        if watch.error.is_none() {
+            for entry in std::fs::read_dir(dir)? {
+                let name = entry?.file_name();
+                if pattern.matches(&name) {
+                    watch.pending.push_back((name, CreationKind::NoEvent));
+                }
+            }
+        }
+
+        match watch.next() {
+            Some(result) => result.map(|(name, file)| (dir.join(name), file)),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "the watched directory was removed")),
+        }
+    }
+
+    /// Opens all files created in `dir` during the next `window` and returns them at its end.
+    ///
+    /// The directory is watched the same way as in `watch_directory` so entries existing before
+    /// this was called are not returned and `assume_create_is_atomic` decides when each file is
+    /// considered created. A file created multiple times, e.g. rewritten, is returned each time.
+    /// The files are returned in the order they were opened.
+    ///
+    /// The window replaces the timeout set in `Options`. If opening any of the files or the
+    /// watch fails, including when the directory is removed, the error is returned and the files
+    /// opened so far are closed.
+    pub fn collect_created_for(&self, dir: &Path, window: Duration) -> io::Result<Vec<(OsString, File)>> {
+        let mut watch = self.watch_directory(dir, |_| true);
+        watch.deadline = Some(self.clock().now() + window);
+        let mut files = Vec::new();
+        loop {
+            match watch.next() {
+                Some(Ok(entry)) => files.push(entry),
+                // The window elapsed
+                Some(Err(error)) if error.kind() == io::ErrorKind::TimedOut => return Ok(files),
+                Some(Err(error)) => return Err(error),
+                None => return Ok(files),
+            }
+        }
+    }
+}
+
+/// Iterator over files created in a directory.
+///
+/// This is returned by `Options::watch_directory`.
+pub struct DirectoryWatch<'a, F> {
+    options: &'a Options,
+    dir: PathBuf,
+    filter: F,
+    // None means polling
+    watcher: Option<backend::DirWatcher>,
+    // Entries seen so far if the names are not reported by the watcher
+    known: Option<HashSet<OsString>>,
+    pending: VecDeque<(OsString, CreationKind)>,
+    // Decides about flukes of the pending entries
+    state: WaitState,
+    // Decides about the delays between rescans when polling
+    polling: WaitState,
+    // `OpenResult::NotReady` if the last rescan found new entries
+    rescanned: OpenResult,
+    buffer: EventBuffer,
+    error: Option<io::Error>,
+    finished: bool,
+    // Overrides the timeout applying to each call
+    deadline: Option<Instant>,
+}
+
+impl<F: Fn(&OsStr) -> bool> DirectoryWatch<'_, F> {
+    fn read_entries(&self) -> io::Result<HashSet<OsString>> {
+        std::fs::read_dir(&self.dir)?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect()
+    }
+
+    /// Compares the directory with previously known entries and queues the new ones.
+    fn rescan(&mut self) -> io::Result<()> {
+        let current = self.read_entries()?;
+        if let Some(known) = &self.known {
+            let filter = &self.filter;
+            self.pending.extend(current.iter().filter(|name| !known.contains(*name) && filter(name.as_os_str())).map(|name| (name.clone(), CreationKind::NoEvent)));
+        }
+        self.known = Some(current);
+        Ok(())
+    }
+
+    /// Opens the pending entries until one of them succeeds.
+    fn open_pending(&mut self, context: &WaitContext<'_>) -> Option<io::Result<(OsString, File)>> {
+        while let Some((name, kind)) = self.pending.pop_front() {
+            let path = self.dir.join(&name);
+            // The entry was seen so it's gone if it can't be found
+            let options = self.options;
+            match check_notified(&mut self.state, Notification::Available(kind), context, |kind| options.attempt_open(&path, kind)) {
+                Ok(Some(file)) => return Some(self.options.wait_unchanged(&path, context).map(|()| (name, file)).map_err(Into::into)),
+                Ok(None) => (),
+                Err(error) => return Some(Err(error.into())),
+            }
+        }
+        None
+    }
+
+    /// Waits for changes and queues the entries which might have been created.
+    fn wait_changes(&mut self, context: &WaitContext<'_>) -> io::Result<()> {
+        let watcher = match &mut self.watcher {
+            Some(watcher) => watcher,
+            None => {
+                if !next_poll(&mut self.polling, self.rescanned, context)? {
+                    return Err(io::Error::other("polling is not enabled"));
+                }
+                let queued = self.pending.len();
+                self.rescan()?;
+                // New entries mean the producer is active so the delay is reset
+                self.rescanned = if self.pending.len() > queued { OpenResult::NotReady } else { OpenResult::NotFound };
+                return Ok(());
+            },
+        };
+
+        match context.wait_readable(&*watcher) {
+            Ok(Wakeup::Readable) => (),
+            Ok(Wakeup::TimedOut) => return Err(timed_out()),
+            Ok(Wakeup::Cancelled) => return Err(cancelled()),
+            Err(error) => return self.fall_back(error),
+        }
+
+        let mut rescan = false;
+        let mut removed = false;
+        let mut created = Vec::new();
+        let result = watcher.read_changes(&mut self.buffer, |_, change| match change {
+            Change::Entry(name, kind) => created.push((name.to_owned(), kind)),
+            Change::Unknown | Change::Overflow => rescan = true,
+            Change::Removed => removed = true,
+        });
+
+        match result {
+            Ok(()) => (),
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => (),
+            Err(error) => return self.fall_back(error),
+        }
+
+        for (name, kind) in created {
+            // Keep the snapshot taken after an overflow up to date to avoid duplicates
+            if let Some(known) = &mut self.known {
+                known.insert(name.clone());
+            }
+            if self.options.is_created(kind) && (self.filter)(&name) {
+                self.pending.push_back((name, kind));
+            }
+        }
+        if rescan {
+            self.rescan()?;
+        }
+        if removed {
+            self.finished = true;
+            return Err(io::Error::new(io::ErrorKind::NotFound, "the watched directory was removed"));
+        }
+        Ok(())
+    }
+
+    /// Switches to polling if it's enabled.
+    fn fall_back(&mut self, error: io::Error) -> io::Result<()> {
+        if self.options.polling_fallback.is_none() {
+            self.finished = true;
+            return Err(error);
+        }
+
+        self.watcher = None;
+        if self.known.is_none() {
+            // Files created before this are missed but there's no way to find them.
+            self.known = Some(self.read_entries()?);
+        }
+        Ok(())
+    }
+}
+
+impl<F: Fn(&OsStr) -> bool> Iterator for DirectoryWatch<'_, F> {
+    type Item = io::Result<(OsString, File)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(error) = self.error.take() {
+            self.finished = true;
+            return Some(Err(error));
+        }
+
+        let context = WaitContext {
+            deadline: self.deadline.or_else(|| self.options.deadline_from(self.options.clock().now())),
+            cancellation: None,
+            clock: self.options.clock(),
+            beats: self.options.beats(self.options.clock().now(), None),
+            mechanism: Cell::new(Mechanism::Inotify),
+            progress: Cell::default(),
+        };
+
+        loop {
+            // Entries queued before the directory was removed are still returned
+            if let Some(result) = self.open_pending(&context) {
+                return Some(result);
+            }
+
+            if self.finished {
+                return None;
+            }
+
+            if let Err(error) = self.wait_changes(&context) {
+                return Some(Err(error));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn watch_directory() {
+        use std::io::Read;
+
+        let options = crate::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone());
+        let polling = options.clone().polling_only(std::time::Duration::from_millis(10));
+
+        for options in [options, polling] {
+            let temp_dir = mktemp::Temp::new_dir().unwrap();
+            std::fs::write(temp_dir.join("existing.job"), "existing").unwrap();
+            let dir_thread = temp_dir.to_path_buf();
+            let thread = std::thread::spawn(move || {
+                // Polling can't tell when the file was closed so the jobs are moved into place
+                let write_job = |name: &str, contents: &str| {
+                    std::fs::write(dir_thread.join("job.tmp"), contents).unwrap();
+                    std::fs::rename(dir_thread.join("job.tmp"), dir_thread.join(name)).unwrap();
+                };
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                std::fs::write(dir_thread.join("ignored.tmp"), "ignored").unwrap();
+                write_job("first.job", "first");
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                write_job("second.job", "second");
+            });
+            let mut watch = options.watch_directory(&temp_dir, |name| name.to_str().is_some_and(|name| name.ends_with(".job")));
+            for expected in ["first", "second"] {
+                let (name, mut file) = watch.next().unwrap().unwrap();
+                let mut contents = String::new();
+                file.read_to_string(&mut contents).unwrap();
+                assert_eq!(name, format!("{}.job", expected).as_str());
+                assert_eq!(contents, expected);
+            }
+            thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn collect_created_for() {
+        use std::io::Read;
+        use std::time::Duration;
+
+        let options = crate::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone());
+        let polling = options.clone().polling_only(Duration::from_millis(10));
+
+        for options in [options, polling] {
+            let temp_dir = mktemp::Temp::new_dir().unwrap();
+            let spool = temp_dir.join("spool");
+            std::fs::create_dir(&spool).unwrap();
+            std::fs::write(spool.join("existing"), "existing").unwrap();
+            let spool_thread = spool.clone();
+            let thread = std::thread::spawn(move || {
+                // Polling can't tell when the file was closed so the files are moved into place
+                for name in ["first", "second", "third"] {
+                    std::thread::sleep(Duration::from_millis(50));
+                    let tmp_path = spool_thread.parent().unwrap().join(name);
+                    std::fs::write(&tmp_path, name).unwrap();
+                    std::fs::rename(&tmp_path, spool_thread.join(name)).unwrap();
+                }
+                std::thread::sleep(Duration::from_millis(500));
+                std::fs::write(spool_thread.join("late"), "late").unwrap();
+            });
+            let files = options.collect_created_for(&spool, Duration::from_millis(400)).unwrap();
+            let contents = files.into_iter().map(|(name, mut file)| {
+                let mut contents = String::new();
+                file.read_to_string(&mut contents).unwrap();
+                assert_eq!(name, contents.as_str());
+                contents
+            }).collect::<Vec<_>>();
+            assert_eq!(contents, ["first", "second", "third"]);
+            thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn open_matching() {
+        use std::io::Read;
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        std::fs::write(temp_dir.join("report.txt"), "ignored").unwrap();
+        let dir_thread = temp_dir.to_path_buf();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(dir_thread.join("report-2021.json"), "report").unwrap();
+        });
+        let options = crate::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone());
+        let (path, mut file) = options.open_matching_when_created(&temp_dir, "report-*.json").unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(path, temp_dir.join("report-2021.json"));
+        assert_eq!(contents, "report");
+        thread.join().unwrap();
+
+        // Existing files are found too
+        let (path, _) = options.open_matching_when_created(&temp_dir, "report-????.json").unwrap();
+        assert_eq!(path, temp_dir.join("report-2021.json"));
+    }
+}
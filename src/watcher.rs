@@ -0,0 +1,589 @@
+//! Low-level API for integration with external event loops.
+
+use std::borrow::Cow;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsRawHandle, RawHandle};
+use std::path::{Path, PathBuf};
+use crate::{Change, ChangeSource, CreationKind, EventBuffer, Notification, Options, Readiness, WaitAction, WaitOutcome, WaitState, Wakeup, backend, cancelled, open_result, scan_changes, take_error, take_opened, timed_out};
+
+impl Options {
+    /// Starts watching the parent directory of `path` without blocking.
+    ///
+    /// This is intended for applications running their own event loop (e.g. using `epoll`) which
+    /// can not dedicate a thread to waiting. See `Watcher` for how to use it.
+    pub fn watcher<P: AsRef<Path>>(&self, path: P) -> io::Result<Watcher<'_>> {
+        Watcher::with_options(Cow::Borrowed(self), path.as_ref())
+    }
+}
+
+/// Watches for a single file driven by an external event loop.
+///
+/// The watch is set up before the file is opened for the first time so no creation can be missed
+/// in between. Use `already_exists()` to find out whether that first open succeeded.
+///
+/// Register the file descriptor returned by `as_raw_fd()` for readability in your event loop and
+/// call `poll_ready()` whenever the descriptor becomes readable. If the file already existed the
+/// first call returns it right away. The descriptor is non-blocking. On Windows `as_raw_handle()`
+/// returns an event handle which is signaled instead. With the `mio` feature enabled the watcher
+/// can be registered with `mio::Poll` directly.
+///
+/// Timeout, polling fallback and waiting for quiescence configured in `Options` are not used
+/// since the event loop is in charge of waiting. Alternatively `wait_event()` can be used to block
+/// until the file changes and decide what to do.
+///
+/// The watcher is `Send` so it can be set up on one thread, e.g. while having elevated privileges,
+/// and moved to another one which does the waiting.
+pub struct Watcher<'a> {
+    options: Cow<'a, Options>,
+    watcher: backend::DirWatcher,
+    watch: backend::WatchId,
+    dir: PathBuf,
+    path: PathBuf,
+    file_name: OsString,
+    buffer: EventBuffer,
+    // The file has to be checked initially and after each relevant change
+    check: bool,
+    kind: CreationKind,
+    state: WaitState,
+    // The file opened when creating the watcher
+    existing: Option<File>,
+    // Identity of the file last returned by `reopen_on_replace`
+    current: Option<(u64, u64)>,
+}
+
+impl Watcher<'static> {
+    /// Starts watching for the file at `path` to be opened for reading.
+    ///
+    /// This uses `Options` with read-only `OpenOptions` and `retry_on_fluke` set to `true`. Use
+    /// `Options::watcher` for other configurations.
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut open_options = OpenOptions::new();
+        open_options.read(true);
+        let options = Options::with_open_options(open_options).retry_on_fluke(true);
+
+        Watcher::with_options(Cow::Owned(options), path.as_ref())
+    }
+}
+
+impl<'a> Watcher<'a> {
+    fn with_options(options: Cow<'a, Options>, path: &Path) -> io::Result<Self> {
+        let (dir, file_name) = options.watch_target(path)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "the path doesn't contain a file name"))?;
+        let (dir, file_name) = (dir.to_path_buf(), file_name.to_owned());
+
+        // Deletions are only reported by `wait_event` but they have to be watched from the start
+        let mut watch_options = Options::clone(&options);
+        watch_options.watch_deletions = true;
+        let mut watcher = backend::DirWatcher::new(&watch_options)?;
+        let watch = watcher.watch(&dir)?;
+        let buffer = EventBuffer::new(&options);
+        let state = wait_state(&options);
+
+        let mut watcher = Watcher {
+            options,
+            watcher,
+            watch,
+            dir,
+            path: path.to_path_buf(),
+            file_name,
+            buffer,
+            check: true,
+            kind: CreationKind::NoEvent,
+            state,
+            existing: None,
+            current: None,
+        };
+        // Only checked after the watch is set up so that creation can't slip in between
+        watcher.existing = watcher.poll_ready()?;
+        Ok(watcher)
+    }
+
+    /// Switches to watching for the file at `new_path`, possibly in a different directory.
+    ///
+    /// This reuses the inotify instance (the kqueue or the event on other platforms) and the
+    /// buffer so the descriptor registered in the event loop stays the same. The new directory is
+    /// watched before the old watch is removed so the watcher is left intact if that fails. The
+    /// changes pending for the old file are discarded and the new file is checked after the watch
+    /// is set up the same way as when the watcher is created, `already_exists()` reports the
+    /// result. The next call to `reopen_on_replace()` returns the new file as if it was called for
+    /// the first time. Failing to remove the old watch is ignored since the kernel removes it on its
+    /// own when the old directory is deleted.
+    ///
+    /// An error with `ErrorKind::NotFound` is returned if the new directory was removed in the
+    /// meantime.
+    pub fn rewatch<P: AsRef<Path>>(&mut self, new_path: P) -> io::Result<()> {
+        let new_path = new_path.as_ref();
+        let (dir, file_name) = self.options.watch_target(new_path)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "the path doesn't contain a file name"))?;
+        let (dir, file_name) = (dir.to_path_buf(), file_name.to_owned());
+
+        let watch = self.watcher.watch(&dir)?;
+        let old = std::mem::replace(&mut self.watch, watch);
+        self.dir = dir;
+        self.path = new_path.to_path_buf();
+        self.file_name = file_name;
+        self.check = true;
+        self.kind = CreationKind::NoEvent;
+        self.state = wait_state(&self.options);
+        self.current = None;
+        // Watching the same directory again returns the same watch on inotify. The kernel may have
+        // already removed the old watch, e.g. if its directory was deleted, so failing is fine.
+        if old != self.watch {
+            let _ = self.watcher.unwatch(old);
+        }
+
+        // Also discards the removal of the old watch which would look like the directory was removed
+        let mut removed = false;
+        let watch = &self.watch;
+        loop {
+            match self.watcher.read_changes(&mut self.buffer, |id, change| if id == watch && matches!(change, Change::Removed) {
+                removed = true;
+            }) {
+                Ok(()) => (),
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(error) => return Err(error),
+            }
+        }
+        if removed {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "the watched directory was removed"));
+        }
+
+        // Only checked after the watch is set up so that creation can't slip in between
+        self.existing = None;
+        self.existing = self.poll_ready()?;
+        Ok(())
+    }
+
+    /// Returns `true` if the file could be opened right when the watcher was created.
+    ///
+    /// In such case the next call to `poll_ready()` returns the file without waiting for the
+    /// descriptor to become readable.
+    pub fn already_exists(&self) -> bool {
+        self.existing.is_some()
+    }
+
+    /// Returns the directory being watched.
+    ///
+    /// This is the parent of the path given when creating the watcher or to `rewatch()`, or `.` if
+    /// it had none, exactly as it was passed to the kernel. Any symlinks in it were resolved when
+    /// the watch was added so later changes to them don't affect which directory is watched. This
+    /// is intended for diagnostics.
+    pub fn watched_dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the inotify watch descriptor of the watched directory.
+    ///
+    /// This is intended for diagnostics, e.g. for correlating with the watches listed in
+    /// `/proc/self/fdinfo/<fd>` where `fd` is the descriptor returned by `as_raw_fd()`.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn watch_descriptor(&self) -> &inotify::WatchDescriptor {
+        &self.watch
+    }
+
+    /// Reads pending changes without blocking and attempts to open the file if it might be
+    /// available.
+    ///
+    /// Returns `Ok(None)` if the file is not ready yet. Errors are fatal and the watcher should be
+    /// dropped after receiving one.
+    pub fn poll_ready(&mut self) -> io::Result<Option<File>> {
+        if let Some(file) = self.existing.take() {
+            return Ok(Some(file));
+        }
+
+        // Draining all changes allows edge-triggered event loops to be used
+        loop {
+            match scan_changes(&mut self.watcher, &mut self.buffer, &self.file_name) {
+                Ok(Some(Readiness::Available(kind))) => {
+                    self.check = true;
+                    self.kind = kind;
+                },
+                // A change reported for the file itself is more specific
+                Ok(Some(Readiness::Maybe)) => if !self.check {
+                    self.check = true;
+                    self.kind = CreationKind::DirectoryChanged;
+                },
+                Ok(None) => (),
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(error) => return Err(error),
+            }
+        }
+
+        if !self.check {
+            return Ok(None);
+        }
+        self.check = false;
+
+        // Only events reporting the file itself mean that it must exist
+        let notification = match self.kind {
+            CreationKind::NoEvent => Notification::Recheck,
+            CreationKind::DirectoryChanged => Notification::Maybe,
+            kind => Notification::Available(kind),
+        };
+        let kind = match self.state.notify(notification) {
+            WaitAction::Open(kind) => kind,
+            action => unreachable!("a notification resulted in {:?}", action),
+        };
+        let mut last = None;
+        match self.state.open_result(open_result(self.options.attempt_open(&self.path, kind), &mut last)) {
+            WaitAction::Return(WaitOutcome::Opened(_)) => Ok(Some(take_opened(&mut last))),
+            WaitAction::Return(outcome) => Err(take_error(&mut last, outcome).into()),
+            // Waiting is up to the caller
+            _ => Ok(None),
+        }
+    }
+}
+
+impl Watcher<'_> {
+    /// Blocks until the next change concerning the file and returns its kind.
+    ///
+    /// Changes of other entries in the directory are skipped. `CreationKind::DirectoryChanged` is
+    /// returned if the platform doesn't report names of the entries or events were lost since the
+    /// file might have changed. Multiple changes read at once are coalesced into the last one.
+    /// Deletion of the file is only reported on platforms using inotify.
+    ///
+    /// The file is not opened, use `Options::try_open_now` or `poll_ready()` which attempts to
+    /// open the file after any change other than deletion. The timeout from `Options` is honored.
+    /// An error with `ErrorKind::NotFound` is returned if the directory was removed.
+    pub fn wait_event(&mut self) -> io::Result<CreationKind> {
+        let context = self.options.wait_context(None);
+        loop {
+            match context.wait_readable(&self.watcher)? {
+                Wakeup::Readable => (),
+                Wakeup::TimedOut => return Err(timed_out()),
+                Wakeup::Cancelled => return Err(cancelled()),
+            }
+
+            let file_name = &self.file_name;
+            let mut found = None;
+            let mut removed = false;
+            let result = self.watcher.read_changes(&mut self.buffer, |_, change| match change {
+                Change::Entry(name, kind) if name == file_name => found = Some(kind),
+                Change::Entry(_, _) => (),
+                Change::Unknown | Change::Overflow => found = Some(CreationKind::DirectoryChanged),
+                Change::Removed => removed = true,
+            });
+            match result {
+                Ok(()) => (),
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(error) => return Err(error),
+            }
+
+            if removed {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "the watched directory was removed"));
+            }
+            if let Some(kind) = found {
+                // The consumed change still has to be taken into account by `poll_ready`
+                if kind != CreationKind::Deleted {
+                    self.check = true;
+                    self.kind = kind;
+                }
+                return Ok(kind);
+            }
+        }
+    }
+
+    /// Returns the file if it was replaced since the last call.
+    ///
+    /// This behaves like `poll_ready()` but after returning a file it returns `Ok(None)` until a
+    /// different file appears at the path, e.g. because the producer atomically replaced it or
+    /// deleted it and created a new one. This allows following a file like `tail -F` does: keep
+    /// reading the returned file and switch to the new one whenever it's returned. Call it whenever
+    /// the descriptor becomes readable, same as `poll_ready()`.
+    ///
+    /// Truncating the file in place doesn't replace it so it's not reported. Check the size of the
+    /// file if the producer may do that.
+    pub fn reopen_on_replace(&mut self) -> io::Result<Option<File>> {
+        let file = match self.poll_ready()? {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        let id = file_id(&file)?;
+        if self.current == Some(id) {
+            return Ok(None);
+        }
+        self.current = Some(id);
+        Ok(Some(file))
+    }
+}
+
+/// Returns the state deciding about flukes of the watcher.
+fn wait_state(options: &Options) -> WaitState {
+    let mut config = options.wait_config();
+    // The watcher never blocks
+    config.fluke_cooldown = None;
+    WaitState::watching(config)
+}
+
+/// Returns the identity of the file which changes when the file at a path is replaced.
+#[cfg(unix)]
+fn file_id(file: &File) -> io::Result<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = file.metadata()?;
+    Ok((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(windows)]
+fn file_id(file: &File) -> io::Result<(u64, u64)> {
+    backend::file_id(file)
+}
+
+#[cfg(unix)]
+impl AsRawFd for Watcher<'_> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.watcher.as_raw_fd()
+    }
+}
+
+#[cfg(windows)]
+impl AsRawHandle for Watcher<'_> {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.watcher.as_raw_handle()
+    }
+}
+
+/// Registers the descriptor returned by `as_raw_fd()` with `mio`.
+///
+/// Only readable interest is meaningful. `poll_ready()` reads all pending changes so it's enough
+/// to call it once after each readiness event even though `mio` is edge-triggered.
+#[cfg(all(unix, feature = "mio"))]
+impl mio::event::Source for Watcher<'_> {
+    fn register(&mut self, registry: &mio::Registry, token: mio::Token, interests: mio::Interest) -> io::Result<()> {
+        mio::unix::SourceFd(&self.as_raw_fd()).register(registry, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &mio::Registry, token: mio::Token, interests: mio::Interest) -> io::Result<()> {
+        mio::unix::SourceFd(&self.as_raw_fd()).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &mio::Registry) -> io::Result<()> {
+        mio::unix::SourceFd(&self.as_raw_fd()).deregister(registry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    #[cfg(unix)]
+    fn poll_ready() {
+        use std::io::Read;
+        use std::os::unix::io::AsRawFd;
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let options = crate::Options::with_open_options(std::fs::OpenOptions::new().read(true).clone());
+        let mut watcher = options.watcher(&file_path).unwrap();
+        assert!(watcher.poll_ready().unwrap().is_none());
+
+        std::fs::write(&file_path, "satoshi nakamoto").unwrap();
+        let mut pollfd = libc::pollfd {
+            fd: watcher.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: we pass a single valid pollfd
+        assert_eq!(unsafe { libc::poll(&mut pollfd, 1, 1000) }, 1);
+        let mut file = watcher.poll_ready().unwrap().unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "satoshi nakamoto");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn rewatch() {
+        use std::io::Read;
+        use std::os::unix::io::AsRawFd;
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let first_dir = temp_dir.join("first");
+        let second_dir = temp_dir.join("second");
+        std::fs::create_dir(&first_dir).unwrap();
+        std::fs::create_dir(&second_dir).unwrap();
+        let mut watcher = super::Watcher::new(first_dir.join("test")).unwrap();
+        let fd = watcher.as_raw_fd();
+        assert!(watcher.poll_ready().unwrap().is_none());
+
+        watcher.rewatch(second_dir.join("test")).unwrap();
+        assert_eq!(watcher.as_raw_fd(), fd);
+        assert_eq!(watcher.watched_dir(), second_dir);
+        assert!(!watcher.already_exists());
+        let mut pollfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // The old directory is not watched anymore
+        std::fs::write(first_dir.join("test"), "first").unwrap();
+        // SAFETY: we pass a single valid pollfd
+        assert_eq!(unsafe { libc::poll(&mut pollfd, 1, 100) }, 0);
+
+        std::fs::write(second_dir.join("test"), "second").unwrap();
+        // SAFETY: we pass a single valid pollfd
+        assert_eq!(unsafe { libc::poll(&mut pollfd, 1, 1000) }, 1);
+        let mut contents = String::new();
+        watcher.poll_ready().unwrap().unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "second");
+
+        // Existing files are returned right away
+        watcher.rewatch(first_dir.join("test")).unwrap();
+        assert!(watcher.already_exists());
+        let mut contents = String::new();
+        watcher.poll_ready().unwrap().unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "first");
+
+        // The old directory being gone doesn't matter
+        std::fs::remove_dir_all(&first_dir).unwrap();
+        watcher.rewatch(second_dir.join("test")).unwrap();
+        assert!(watcher.already_exists());
+        assert_eq!(watcher.watched_dir(), &*second_dir);
+    }
+
+    #[test]
+    fn send_to_other_thread() {
+        fn assert_send<T: Send>(_: &T) {}
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let watcher = super::Watcher::new(&file_path).unwrap();
+        assert_send(&watcher);
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            let mut watcher = watcher;
+            loop {
+                let _ = watcher.wait_event().unwrap();
+                if let Some(file) = watcher.poll_ready().unwrap() {
+                    return file.metadata().unwrap().len();
+                }
+            }
+        });
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        std::fs::write(&file_path_thread, "satoshi nakamoto").unwrap();
+        assert_eq!(thread.join().unwrap(), 16);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn reopen_on_replace() {
+        use std::io::{Read, Write};
+        use std::os::unix::io::AsRawFd;
+
+        fn wait_readable(watcher: &super::Watcher<'_>) {
+            let mut pollfd = libc::pollfd {
+                fd: watcher.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: we pass a single valid pollfd
+            unsafe { libc::poll(&mut pollfd, 1, 1000) };
+        }
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        std::fs::write(&file_path, "first").unwrap();
+        let mut watcher = super::Watcher::new(&file_path).unwrap();
+        let mut first = watcher.reopen_on_replace().unwrap().unwrap();
+
+        // Appending keeps the same file
+        std::fs::OpenOptions::new().append(true).open(&file_path).unwrap().write_all(b" more").unwrap();
+        wait_readable(&watcher);
+        assert!(watcher.reopen_on_replace().unwrap().is_none());
+        let mut contents = String::new();
+        first.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "first more");
+
+        let temp_path = temp_dir.join("test.tmp");
+        std::fs::write(&temp_path, "second").unwrap();
+        std::fs::rename(&temp_path, &file_path).unwrap();
+        wait_readable(&watcher);
+        let mut second = watcher.reopen_on_replace().unwrap().unwrap();
+        let mut contents = String::new();
+        second.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "second");
+    }
+
+    #[test]
+    #[cfg(all(unix, feature = "mio"))]
+    fn mio_source() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let mut watcher = super::Watcher::new(&file_path).unwrap();
+        let mut poll = mio::Poll::new().unwrap();
+        poll.registry().register(&mut watcher, mio::Token(42), mio::Interest::READABLE).unwrap();
+
+        // Many changes at once to make sure they are all drained
+        for i in 0..200 {
+            std::fs::write(temp_dir.join(format!("other file with a long name {}", i)), "").unwrap();
+        }
+        std::fs::write(&file_path, "satoshi nakamoto").unwrap();
+        let mut events = mio::Events::with_capacity(1);
+        poll.poll(&mut events, Some(std::time::Duration::from_secs(1))).unwrap();
+        assert_eq!(events.iter().next().unwrap().token(), mio::Token(42));
+        let file = watcher.poll_ready().unwrap().unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 16);
+        poll.registry().deregister(&mut watcher).unwrap();
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn wait_event() {
+        use crate::CreationKind;
+
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let mut watcher = super::Watcher::new(&file_path).unwrap();
+        let file_path_thread = file_path.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::write(file_path_thread.with_extension("tmp"), "satoshi nakamoto").unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::rename(file_path_thread.with_extension("tmp"), &file_path_thread).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            std::fs::remove_file(&file_path_thread).unwrap();
+        });
+        assert_eq!(watcher.wait_event().unwrap(), CreationKind::MovedTo);
+        assert!(watcher.poll_ready().unwrap().is_some());
+        assert_eq!(watcher.wait_event().unwrap(), CreationKind::Deleted);
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn already_exists() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let file_path = temp_dir.join("test");
+        let mut watcher = super::Watcher::new(&file_path).unwrap();
+        assert!(!watcher.already_exists());
+        assert!(watcher.poll_ready().unwrap().is_none());
+
+        std::fs::write(&file_path, "satoshi nakamoto").unwrap();
+        let mut watcher = super::Watcher::new(&file_path).unwrap();
+        assert!(watcher.already_exists());
+        assert!(watcher.poll_ready().unwrap().is_some());
+        assert!(watcher.poll_ready().unwrap().is_none());
+    }
+
+    #[test]
+    fn watched_dir() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let watcher = super::Watcher::new(temp_dir.join("test")).unwrap();
+        assert_eq!(watcher.watched_dir(), &*temp_dir);
+        let watcher = super::Watcher::new("test").unwrap();
+        assert_eq!(watcher.watched_dir(), std::path::Path::new("."));
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            use std::os::unix::io::AsRawFd;
+
+            let fdinfo = std::fs::read_to_string(format!("/proc/self/fdinfo/{}", watcher.as_raw_fd())).unwrap();
+            let watches = fdinfo.lines().filter(|line| line.starts_with("inotify wd:")).count();
+            assert_eq!(watches, 1);
+            let _: &inotify::WatchDescriptor = watcher.watch_descriptor();
+        }
+    }
+}
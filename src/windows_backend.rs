@@ -0,0 +1,359 @@
+//! Watching directories using `ReadDirectoryChangesW`.
+//!
+//! Each watched directory has an overlapped read pending all the time. All of them signal the same
+//! manual-reset event which is what the waiting code waits for. Windows doesn't report closing of
+//! files so creation of a file is reported as soon as it appears in the directory.
+
+use std::ffi::{c_void, OsString};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::windows::ffi::OsStringExt;
+use std::os::windows::fs::OpenOptionsExt;
+use std::os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle, RawHandle};
+use std::path::Path;
+use std::ptr;
+use crate::{Change, ChangeSource, CreationKind, Options};
+
+pub(crate) type WatchId = usize;
+
+/// `ReadDirectoryChangesW` reports names of changed entries.
+pub(crate) const REPORTS_NAMES: bool = true;
+
+/// `ReadDirectoryChangesW` doesn't report closing of files.
+pub(crate) const REPORTS_CLOSE_WRITE: bool = false;
+
+// Constants from the Windows SDK
+const FILE_LIST_DIRECTORY: u32 = 0x0001;
+const FILE_SHARE_READ: u32 = 0x0001;
+const FILE_SHARE_WRITE: u32 = 0x0002;
+const FILE_SHARE_DELETE: u32 = 0x0004;
+const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+const FILE_FLAG_OVERLAPPED: u32 = 0x4000_0000;
+const FILE_NOTIFY_CHANGE_FILE_NAME: u32 = 0x0001;
+const FILE_NOTIFY_CHANGE_DIR_NAME: u32 = 0x0002;
+const FILE_NOTIFY_CHANGE_ATTRIBUTES: u32 = 0x0004;
+const FILE_NOTIFY_CHANGE_SIZE: u32 = 0x0008;
+const FILE_NOTIFY_CHANGE_LAST_WRITE: u32 = 0x0010;
+const FILE_NOTIFY_CHANGE_SECURITY: u32 = 0x0100;
+const FILE_ACTION_ADDED: u32 = 1;
+const FILE_ACTION_MODIFIED: u32 = 3;
+const FILE_ACTION_RENAMED_NEW_NAME: u32 = 5;
+const ERROR_IO_INCOMPLETE: i32 = 996;
+const STATUS_PENDING: usize = 0x103;
+const WAIT_TIMEOUT: u32 = 258;
+const WAIT_FAILED: u32 = 0xFFFF_FFFF;
+const INFINITE: u32 = 0xFFFF_FFFF;
+
+/// Opens directories so that they can be passed to `open_when_created` and friends.
+pub(crate) const FILE_FLAG_OPEN_DIRECTORY: u32 = FILE_FLAG_BACKUP_SEMANTICS;
+/// Opens the symlink itself rather than its target.
+pub(crate) const FILE_FLAG_OPEN_REPARSE_POINT: u32 = 0x0020_0000;
+/// Reported when the path is a symlink and symlinks are not followed.
+pub(crate) const ERROR_CANT_RESOLVE_FILENAME: i32 = 1921;
+/// Reported when a directory was expected.
+pub(crate) const ERROR_DIRECTORY: i32 = 267;
+pub(crate) const ERROR_TOO_MANY_OPEN_FILES: i32 = 4;
+pub(crate) const ERROR_NOT_ENOUGH_QUOTA: i32 = 1816;
+
+#[repr(C)]
+struct Overlapped {
+    internal: usize,
+    internal_high: usize,
+    offset: u32,
+    offset_high: u32,
+    event: RawHandle,
+}
+
+#[repr(C)]
+struct FileNotifyInformation {
+    next_entry_offset: u32,
+    action: u32,
+    file_name_length: u32,
+    file_name: [u16; 1],
+}
+
+#[repr(C)]
+struct ByHandleFileInformation {
+    file_attributes: u32,
+    creation_time: [u32; 2],
+    last_access_time: [u32; 2],
+    last_write_time: [u32; 2],
+    volume_serial_number: u32,
+    file_size_high: u32,
+    file_size_low: u32,
+    number_of_links: u32,
+    file_index_high: u32,
+    file_index_low: u32,
+}
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn ReadDirectoryChangesW(directory: RawHandle, buffer: *mut c_void, buffer_length: u32, watch_subtree: i32, notify_filter: u32, bytes_returned: *mut u32, overlapped: *mut Overlapped, completion_routine: *mut c_void) -> i32;
+    fn GetOverlappedResult(file: RawHandle, overlapped: *mut Overlapped, bytes_transferred: *mut u32, wait: i32) -> i32;
+    fn CancelIoEx(file: RawHandle, overlapped: *mut Overlapped) -> i32;
+    fn CreateEventW(attributes: *mut c_void, manual_reset: i32, initial_state: i32, name: *const u16) -> RawHandle;
+    fn SetEvent(event: RawHandle) -> i32;
+    fn ResetEvent(event: RawHandle) -> i32;
+    fn WaitForMultipleObjects(count: u32, handles: *const RawHandle, wait_all: i32, milliseconds: u32) -> u32;
+    fn GetFileInformationByHandle(file: RawHandle, information: *mut ByHandleFileInformation) -> i32;
+}
+
+/// Returns the volume serial number and the index of the file which together identify it.
+pub(crate) fn file_id(file: &File) -> io::Result<(u64, u64)> {
+    let mut information = std::mem::MaybeUninit::<ByHandleFileInformation>::uninit();
+    // SAFETY: the handle is valid and the pointer points to a structure of the right size
+    if unsafe { GetFileInformationByHandle(file.as_raw_handle(), information.as_mut_ptr()) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the call succeeded so the structure was filled
+    let information = unsafe { information.assume_init() };
+    let index = u64::from(information.file_index_high) << 32 | u64::from(information.file_index_low);
+    Ok((information.volume_serial_number.into(), index))
+}
+
+/// Creates a manual-reset event which is not signaled.
+pub(crate) fn create_event() -> io::Result<OwnedHandle> {
+    // SAFETY: null attributes and name are allowed
+    let event = unsafe { CreateEventW(ptr::null_mut(), 1, 0, ptr::null()) };
+    if event.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the event was just created and nothing else owns it
+    Ok(unsafe { OwnedHandle::from_raw_handle(event) })
+}
+
+pub(crate) fn set_event(event: &OwnedHandle) -> io::Result<()> {
+    // SAFETY: the handle is a valid event
+    if unsafe { SetEvent(event.as_raw_handle()) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Waits until any of the handles is signaled returning its index.
+///
+/// `None` timeout means waiting forever. Returns `Ok(None)` if the wait timed out.
+pub(crate) fn wait_any(handles: &[RawHandle], timeout_ms: Option<u32>) -> io::Result<Option<usize>> {
+    // The timeout must not be confused with INFINITE
+    let timeout_ms = timeout_ms.map_or(INFINITE, |timeout| timeout.min(INFINITE - 1));
+    // SAFETY: the pointer and length come from a valid slice of handles
+    match unsafe { WaitForMultipleObjects(handles.len() as u32, handles.as_ptr(), 0, timeout_ms) } {
+        WAIT_TIMEOUT => Ok(None),
+        WAIT_FAILED => Err(io::Error::last_os_error()),
+        // WAIT_OBJECT_0 is zero
+        index if index < handles.len() as u32 => Ok(Some(index as usize)),
+        // Abandoned mutexes are never passed here
+        _ => Err(io::Error::other("unexpected result of WaitForMultipleObjects")),
+    }
+}
+
+/// Single watched directory with its pending read.
+struct Watch {
+    dir: File,
+    // Boxed since the kernel writes to them while the read is pending
+    overlapped: Box<Overlapped>,
+    buffer: Box<[u32; 4096]>,
+}
+
+// SAFETY: the raw pointers only refer to the owned boxes and the event owned by the DirWatcher.
+// They are only accessed through `&mut self`.
+unsafe impl Send for Watch {}
+// SAFETY: see above, nothing is accessed through a shared reference
+unsafe impl Sync for Watch {}
+
+impl Watch {
+    /// Starts the overlapped read.
+    fn read(&mut self, filter: u32) -> io::Result<()> {
+        let buffer_length = std::mem::size_of_val(&*self.buffer) as u32;
+        // SAFETY: the buffer is DWORD-aligned as required and both it and the overlapped structure
+        // live until the read completes or is cancelled in Drop
+        let result = unsafe {
+            ReadDirectoryChangesW(self.dir.as_raw_handle(), self.buffer.as_mut_ptr() as *mut c_void, buffer_length, 0, filter, ptr::null_mut(), &mut *self.overlapped, ptr::null_mut())
+        };
+        if result == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Returns the number of bytes read if the pending read completed.
+    fn poll(&mut self) -> io::Result<Option<u32>> {
+        let mut bytes = 0;
+        // SAFETY: the overlapped structure belongs to a read issued on this handle
+        if unsafe { GetOverlappedResult(self.dir.as_raw_handle(), &mut *self.overlapped, &mut bytes, 0) } == 0 {
+            let error = io::Error::last_os_error();
+            if error.raw_os_error() == Some(ERROR_IO_INCOMPLETE) {
+                return Ok(None);
+            }
+            return Err(error);
+        }
+        Ok(Some(bytes))
+    }
+
+    fn is_completed(&self) -> bool {
+        // SAFETY: the field is valid for reads, volatile since the kernel writes it concurrently
+        unsafe { ptr::read_volatile(&self.overlapped.internal) != STATUS_PENDING }
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        // SAFETY: the overlapped structure belongs to a read issued on this handle
+        unsafe { CancelIoEx(self.dir.as_raw_handle(), &mut *self.overlapped); }
+        // The buffers must not be freed before the kernel stops using them
+        while !self.is_completed() {
+            std::thread::yield_now();
+        }
+    }
+}
+
+/// Watches directories using a single event signaled by all of them.
+pub(crate) struct DirWatcher {
+    // Dropped before the event they refer to
+    watches: Vec<Option<Watch>>,
+    event: OwnedHandle,
+    filter: u32,
+}
+
+impl DirWatcher {
+    pub(crate) fn new(options: &Options) -> io::Result<Self> {
+        let mut filter = FILE_NOTIFY_CHANGE_FILE_NAME | FILE_NOTIFY_CHANGE_DIR_NAME;
+        if options.watches_modifications() {
+            filter |= FILE_NOTIFY_CHANGE_SIZE | FILE_NOTIFY_CHANGE_LAST_WRITE;
+        }
+        if options.retry_permission_denied {
+            filter |= FILE_NOTIFY_CHANGE_ATTRIBUTES | FILE_NOTIFY_CHANGE_SECURITY;
+        }
+
+        Ok(DirWatcher {
+            watches: Vec::new(),
+            event: create_event()?,
+            filter,
+        })
+    }
+
+    pub(crate) fn watch(&mut self, dir: &Path) -> io::Result<WatchId> {
+        let dir = OpenOptions::new()
+            .access_mode(FILE_LIST_DIRECTORY)
+            .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE)
+            .custom_flags(FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OVERLAPPED)
+            .open(dir)?;
+
+        let overlapped = Overlapped {
+            internal: 0,
+            internal_high: 0,
+            offset: 0,
+            offset_high: 0,
+            event: self.event.as_raw_handle(),
+        };
+        let mut watch = Watch {
+            dir,
+            overlapped: Box::new(overlapped),
+            buffer: Box::new([0; 4096]),
+        };
+        watch.read(self.filter)?;
+
+        self.watches.push(Some(watch));
+        Ok(self.watches.len() - 1)
+    }
+
+    pub(crate) fn unwatch(&mut self, watch: WatchId) -> io::Result<()> {
+        // Dropping the watch cancels the pending read, the slot is kept so that the IDs stay valid
+        match self.watches.get_mut(watch).and_then(Option::take) {
+            Some(_) => Ok(()),
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "the directory is not watched")),
+        }
+    }
+}
+
+impl ChangeSource for DirWatcher {
+    fn read_changes<F: FnMut(&WatchId, Change<'_>)>(&mut self, _buffer: &mut [u8], mut f: F) -> io::Result<()> {
+        // Each watch has its own buffer since the reads are asynchronous
+        let mut received = false;
+        // SAFETY: the handle is a valid event
+        if unsafe { ResetEvent(self.event.as_raw_handle()) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        for (id, slot) in self.watches.iter_mut().enumerate() {
+            let watch = match slot {
+                Some(watch) => watch,
+                None => continue,
+            };
+
+            let bytes = match watch.poll() {
+                Ok(Some(bytes)) => bytes,
+                Ok(None) => continue,
+                // The directory was most likely deleted
+                Err(_) => {
+                    received = true;
+                    *slot = None;
+                    f(&id, Change::Removed);
+                    continue;
+                },
+            };
+
+            received = true;
+            if bytes == 0 {
+                // The buffer was too small to hold all the changes
+                f(&id, Change::Overflow);
+            } else {
+                parse_changes(&watch.buffer[..], bytes as usize, |name, kind| f(&id, Change::Entry(name, kind)));
+            }
+
+            if watch.read(self.filter).is_err() {
+                *slot = None;
+                f(&id, Change::Removed);
+            }
+        }
+
+        // Starting a read resets the event so it could have hidden completion of another one
+        if self.watches.iter().flatten().any(Watch::is_completed) {
+            set_event(&self.event)?;
+        }
+
+        if received {
+            Ok(())
+        } else {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+    }
+}
+
+impl AsRawHandle for DirWatcher {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.event.as_raw_handle()
+    }
+}
+
+/// Calls `f` for each record in the buffer filled by `ReadDirectoryChangesW`.
+fn parse_changes<F: FnMut(&std::ffi::OsStr, CreationKind)>(buffer: &[u32], len: usize, mut f: F) {
+    let base = buffer.as_ptr() as *const u8;
+    let len = len.min(std::mem::size_of_val(buffer));
+    let mut offset = 0;
+    while offset + std::mem::size_of::<FileNotifyInformation>() <= len {
+        // SAFETY: the kernel aligns the records to DWORD and the record header is in bounds
+        let record = unsafe { &*(base.add(offset) as *const FileNotifyInformation) };
+        let name_len = record.file_name_length as usize / 2;
+        let name_offset = offset + std::mem::size_of::<u32>() * 3;
+        if name_offset + name_len * 2 > len {
+            break;
+        }
+        // SAFETY: the name was checked to be in bounds and is aligned to u16
+        let name = unsafe { std::slice::from_raw_parts(base.add(name_offset) as *const u16, name_len) };
+        let kind = match record.action {
+            FILE_ACTION_ADDED => Some(CreationKind::Created),
+            FILE_ACTION_RENAMED_NEW_NAME => Some(CreationKind::MovedTo),
+            FILE_ACTION_MODIFIED => Some(CreationKind::Modified),
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            f(&OsString::from_wide(name), kind);
+        }
+
+        if record.next_entry_offset == 0 {
+            break;
+        }
+        offset += record.next_entry_offset as usize;
+    }
+}